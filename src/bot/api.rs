@@ -0,0 +1,393 @@
+//! Embedded HTTP server with a JSON API for dashboards and scripts.
+//!
+//! Requests carry `Authorization: Bearer <token>` and act as the user of the token, so
+//! roles limit them like commands in chats:
+//!
+//! - `GET /api/torrents?instance=<name>` lists torrents of the instance, the first by default
+//! - `POST /api/torrents` with `{"link": "magnet:...", "instance": "<name>", "subscribe": true}`
+//!   adds a torrent, `instance` and `subscribe` are optional
+//! - `POST /api/torrents/<hash>/pause` and `POST /api/torrents/<hash>/resume`
+//! - `DELETE /api/torrents/<hash>?files=true` deletes a torrent, files are kept by default
+//! - `POST /api/torrents/<hash>/subscribe` notifies the chat of the token when it's done
+//!
+//! Torrents known by hash are looked up in all instances unless `?instance=` is set.
+//! Changes are written to the audit log with the token user and name, added torrents are
+//! tagged with the token user. Tokens are read from the running config, so `/reload`
+//! applies added and revoked ones.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::bot::audit::{Actor, AuditEntry};
+use crate::bot::commands::download::QDownloadAction;
+use crate::bot::commands::list::QbListRecord;
+use crate::bot::commands::pause_resume::QPauseResumeAction;
+use crate::bot::commands::registry;
+use crate::bot::commands::QbCommandAction;
+use crate::bot::config::{ApiConfig, ApiToken, Role};
+use crate::bot::http::{constant_time_eq, Handler, HttpServer};
+use crate::bot::qb_client::QbClient;
+use crate::bot::qbot::{QbitBot, NOT_OWNER_MESSAGE};
+use crate::bot::watchers::Watch;
+
+/// Larger request bodies are rejected, a torrent link is much shorter
+const MAX_BODY: usize = 64 * 1024;
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// The torrent client failed or is unreachable
+    fn upstream(err: anyhow::Error) -> Self {
+        Self::new(StatusCode::BAD_GATEWAY, format!("{:#}", err))
+    }
+}
+
+#[derive(Deserialize)]
+struct AddRequest {
+    link: String,
+    instance: Option<String>,
+    #[serde(default)]
+    subscribe: bool,
+}
+
+struct ApiHandler {
+    qbot: Arc<QbitBot>,
+}
+
+impl ApiHandler {
+    fn respond(status: StatusCode, body: Value) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Token of the request and the current role of its user, reload changes both
+    fn authorize(&self, req: &Request<Body>) -> Result<(ApiToken, Role), ApiError> {
+        let config = self.qbot.config();
+        let bearer = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let token = bearer
+            .and_then(|bearer| {
+                config
+                    .api
+                    .as_ref()?
+                    .tokens
+                    .iter()
+                    .find(|token| constant_time_eq(token.token.as_bytes(), bearer.as_bytes()))
+            })
+            .cloned()
+            .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Missing or unknown token"))?;
+        match config.role(&token.user) {
+            Some(role) => Ok((token, role)),
+            None => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("User {} is not allowed", token.user),
+            )),
+        }
+    }
+
+    /// Same check as for the chat command
+    fn allow(role: Role, command: &str) -> Result<(), ApiError> {
+        let spec = registry::find(command).expect("API actions are bot commands");
+        if role.allows(spec) {
+            Ok(())
+        } else {
+            Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("You are not allowed to use /{}", command),
+            ))
+        }
+    }
+
+    async fn route(
+        &self,
+        token: &ApiToken,
+        role: Role,
+        req: Request<Body>,
+    ) -> Result<Value, ApiError> {
+        let url = Url::parse(&format!("http://localhost{}", req.uri()))
+            .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let instance = query.get("instance").map(String::as_str);
+        let segments: Vec<&str> = url.path().trim_matches('/').split('/').collect();
+        let method = req.method().clone();
+        match (method, segments.as_slice()) {
+            (Method::GET, ["api", "torrents"]) => {
+                Self::allow(role, "list")?;
+                let client = self.client(instance)?;
+                let list = client.get_cached_list().await.map_err(ApiError::upstream)?;
+                Ok(json!({ "instance": client.name(), "torrents": list.get_records() }))
+            }
+            (Method::POST, ["api", "torrents"]) => {
+                Self::allow(role, "download")?;
+                let body = Self::read_body(req.into_body()).await?;
+                let add: AddRequest = serde_json::from_slice(&body).map_err(|err| {
+                    ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", err))
+                })?;
+                self.add(token, add).await
+            }
+            (Method::POST, ["api", "torrents", hash, action])
+                if *action == "pause" || *action == "resume" =>
+            {
+                Self::allow(role, action)?;
+                let (client, record) = self.torrent(hash, instance).await?;
+                self.check_owner(token, role, &record)?;
+                let res = QPauseResumeAction::new(action).act_hash(client, hash).await;
+                let entry = Self::audit_entry(token, action, client);
+                self.qbot.audit().record(
+                    entry
+                        .target(hash, Some(record.get_name()))
+                        .outcome(res.action_result_to_string()),
+                );
+                if res.is_ok() {
+                    Ok(json!({ "status": "OK" }))
+                } else {
+                    Err(ApiError::new(
+                        StatusCode::BAD_GATEWAY,
+                        res.action_result_to_string(),
+                    ))
+                }
+            }
+            (Method::POST, ["api", "torrents", hash, "subscribe"]) => {
+                Self::allow(role, "list")?;
+                let chat_id = Self::chat_id(token)?;
+                let (client, record) = self.torrent(hash, instance).await?;
+                self.subscribe(client, chat_id, hash, record.get_name());
+                Ok(json!({ "status": "OK" }))
+            }
+            (Method::DELETE, ["api", "torrents", hash]) => {
+                if !role.can_change() {
+                    return Err(ApiError::new(
+                        StatusCode::FORBIDDEN,
+                        "You are not allowed to delete torrents",
+                    ));
+                }
+                let files = query.get("files").map(String::as_str) == Some("true");
+                let (client, record) = self.torrent(hash, instance).await?;
+                self.check_owner(token, role, &record)?;
+                let res = client.backend().delete(hash, files).await;
+                let entry = Self::audit_entry(token, "delete", client)
+                    .target(hash, Some(record.get_name()))
+                    .param("files", files.to_string());
+                let outcome = match &res {
+                    Ok(()) => String::from("OK"),
+                    Err(err) => format!("{:#}", err),
+                };
+                self.qbot.audit().record(entry.outcome(outcome));
+                res.map_err(ApiError::upstream)?;
+                self.qbot.unwatch(client.name(), hash);
+                Ok(json!({ "status": "OK" }))
+            }
+            _ => Err(ApiError::new(StatusCode::NOT_FOUND, "Not found")),
+        }
+    }
+
+    /// Body of at most [MAX_BODY] bytes
+    async fn read_body(mut body: Body) -> Result<Vec<u8>, ApiError> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk =
+                chunk.map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
+            if bytes.len() + chunk.len() > MAX_BODY {
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Request body is larger than {} bytes", MAX_BODY),
+                ));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+
+    async fn add(&self, token: &ApiToken, add: AddRequest) -> Result<Value, ApiError> {
+        let chat_id = if add.subscribe {
+            Some(Self::chat_id(token)?)
+        } else {
+            None
+        };
+        let client = self.client(add.instance.as_deref())?;
+        let entry = Self::audit_entry(token, "download", client).param("link", add.link.as_str());
+        let action = match QDownloadAction::default()
+            .send_link(client, &add.link, Some(&token.user))
+            .await
+        {
+            Ok(action) => action,
+            Err(err) => {
+                self.qbot
+                    .audit()
+                    .record(entry.outcome(format!("{:#}", err)));
+                return Err(ApiError::upstream(err));
+            }
+        };
+        let hash = match action.hash() {
+            Some(hash) => hash,
+            None => {
+                self.qbot
+                    .audit()
+                    .record(entry.outcome(action.action_result_to_string()));
+                return Err(ApiError::new(StatusCode::CONFLICT, "Torrent was not added"));
+            }
+        };
+        let name = QDownloadAction::get_name(client, hash).await;
+        let entry = entry.target(hash, name.clone());
+        self.qbot
+            .audit()
+            .record(entry.outcome(action.action_result_to_string()));
+        if let Some(chat_id) = chat_id {
+            match name {
+                Some(name) => self.subscribe(client, chat_id, hash, name),
+                None => error!("Failed to get torrent name"),
+            }
+        }
+        Ok(json!({ "status": action.action_result_to_string(), "hash": hash }))
+    }
+
+    /// Same rule as in chats when `own_torrents_only` is set
+    fn check_owner(
+        &self,
+        token: &ApiToken,
+        role: Role,
+        record: &QbListRecord,
+    ) -> Result<(), ApiError> {
+        if self.qbot.config().restricts_to_own(role) && !record.is_owned_by(&token.user) {
+            Err(ApiError::new(StatusCode::FORBIDDEN, NOT_OWNER_MESSAGE))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Entry of a change made with `token`, its name is kept as a parameter
+    fn audit_entry(token: &ApiToken, command: &str, client: &QbClient) -> AuditEntry {
+        let actor = Actor {
+            user_id: None,
+            username: token.user.clone(),
+            chat_id: token.chat_id,
+        };
+        AuditEntry::new(&actor, command, client).param("token", token.name.as_str())
+    }
+
+    fn chat_id(token: &ApiToken) -> Result<i64, ApiError> {
+        token.chat_id.ok_or_else(|| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Token {} has no chat_id for notifications", token.name),
+            )
+        })
+    }
+
+    fn subscribe(&self, client: &Arc<QbClient>, chat_id: i64, hash: &str, name: String) {
+        let watch = Watch {
+            chat_id,
+            fallback_chat_id: None,
+            instance: client.name().to_string(),
+            hash: hash.to_string(),
+            name,
+        };
+        self.qbot.watch(client.clone(), watch);
+    }
+
+    /// Instance by name, the first one by default like in chats
+    fn client(&self, name: Option<&str>) -> Result<&Arc<QbClient>, ApiError> {
+        let instances = self.qbot.instances();
+        match name {
+            Some(name) => instances
+                .iter()
+                .find(|client| client.name() == name)
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::NOT_FOUND,
+                        format!("There is no instance {}", name),
+                    )
+                }),
+            None => Ok(&instances[0]),
+        }
+    }
+
+    /// Instance with the torrent, unreachable ones are skipped unless `instance` is set
+    async fn torrent(
+        &self,
+        hash: &str,
+        instance: Option<&str>,
+    ) -> Result<(&Arc<QbClient>, QbListRecord), ApiError> {
+        let clients = match instance {
+            Some(_) => vec![self.client(instance)?],
+            None => self.qbot.instances().iter().collect(),
+        };
+        for client in clients {
+            let list = match client.get_cached_list().await {
+                Ok(list) => list,
+                Err(err) if instance.is_some() => return Err(ApiError::upstream(err)),
+                Err(err) => {
+                    warn!("Failed to get list of {}: {:#}", client.name(), err);
+                    continue;
+                }
+            };
+            let record = list
+                .get_records()
+                .iter()
+                .find(|record| record.get_hash() == hash);
+            if let Some(record) = record {
+                return Ok((client, record.clone()));
+            }
+        }
+        Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("There is no torrent {}", hash),
+        ))
+    }
+}
+
+#[async_trait]
+impl Handler for ApiHandler {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let res = match self.authorize(&req) {
+            Ok((token, role)) => {
+                info!(
+                    "API {} {} by {}",
+                    req.method(),
+                    req.uri().path(),
+                    token.name
+                );
+                self.route(&token, role, req).await
+            }
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(body) => Self::respond(StatusCode::OK, body),
+            Err(err) => {
+                if err.status.is_server_error() {
+                    warn!("API request failed: {}", err.message);
+                }
+                Self::respond(err.status, json!({ "error": err.message }))
+            }
+        }
+    }
+}
+
+/// Server of the JSON API, tokens are read from the running config
+pub fn bind(config: &ApiConfig, qbot: Arc<QbitBot>) -> Result<HttpServer> {
+    HttpServer::bind(&config.listen, ApiHandler { qbot })
+}
@@ -0,0 +1,150 @@
+//! Append-only log of actions that change torrents, one JSON object per line
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::bot::qb_client::QbClient;
+
+/// Who runs a command, API requests have no Telegram user id
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Actor {
+    pub user_id: Option<i64>,
+    pub username: String,
+    pub chat_id: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// RFC 3339 local time
+    pub time: String,
+    pub user_id: Option<i64>,
+    pub username: String,
+    pub chat_id: Option<i64>,
+    pub command: String,
+    /// Name of the torrent client instance
+    pub instance: String,
+    pub hash: Option<String>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    pub outcome: String,
+}
+
+impl AuditEntry {
+    pub fn new(actor: &Actor, command: &str, client: &QbClient) -> Self {
+        Self {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            user_id: actor.user_id,
+            username: actor.username.clone(),
+            chat_id: actor.chat_id,
+            command: command.to_string(),
+            instance: client.name().to_string(),
+            hash: None,
+            name: None,
+            params: BTreeMap::new(),
+            outcome: String::new(),
+        }
+    }
+
+    pub fn target(mut self, hash: &str, name: Option<String>) -> Self {
+        self.hash = Some(hash.to_string());
+        self.name = name;
+        self
+    }
+
+    pub fn param(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.params.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn outcome(mut self, outcome: impl Into<String>) -> Self {
+        self.outcome = outcome.into();
+        self
+    }
+
+    /// One line for `/audit`, e.g. `2021-06-01T12:00:00+03:00 alice /pause Ubuntu dl=2M: OK`
+    pub fn summary(&self) -> String {
+        let mut words = vec![self.time.clone(), self.username.clone()];
+        words.push(format!("/{}", self.command));
+        if let Some(target) = self.name.as_ref().or(self.hash.as_ref()) {
+            words.push(target.clone());
+        }
+        words.extend(
+            self.params
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value)),
+        );
+        format!("{}: {}", words.join(" "), self.outcome)
+    }
+}
+
+/// Log of local terminal sessions next to the log at `path`, e.g. `audit.repl.jsonl`,
+/// so they don't mix with changes made through the bot
+pub fn repl_path(path: &str) -> String {
+    Path::new(path)
+        .with_extension("repl.jsonl")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub struct AuditLog {
+    path: String,
+    /// Lines of concurrent commands must not interleave
+    file: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            file: Mutex::new(()),
+        }
+    }
+
+    /// Failures are logged, the action is done anyway
+    pub fn record(&self, entry: AuditEntry) {
+        info!("Audit: {}", entry.summary());
+        if let Err(err) = self.append(&entry) {
+            error!("Failed to write audit log: {:#}", err);
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> Result<()> {
+        let line = serde_json::to_string(entry)?;
+        let _lock = self.file.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path))?;
+        writeln!(file, "{}", line).with_context(|| format!("Failed to write {}", self.path))
+    }
+
+    /// Last `count` entries, oldest first. Broken lines are skipped.
+    pub fn recent(&self, count: usize) -> Result<Vec<AuditEntry>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).with_context(|| format!("Failed to read {}", self.path)),
+        };
+        let mut entries: Vec<AuditEntry> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    warn!("Skipped broken audit log line: {}", err);
+                    None
+                }
+            })
+            .collect();
+        let skip = entries.len().saturating_sub(count);
+        Ok(entries.split_off(skip))
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt::{self, Display};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use super::config::{BackendKind, InstanceConfig};
+use super::sync::{SyncState, TorrentFields};
+
+pub mod qbittorrent;
+pub mod transmission;
+
+pub use qbittorrent::QbittorrentBackend;
+pub use transmission::TransmissionBackend;
+
+/// Torrent client API used by the bot.
+///
+/// Torrents are described with Qbittorrent field names (`hash`, `name`, `size`, `progress`,
+/// `eta`, `completion_on`, `state`, `ratio`, `tags`), other backends translate their own
+/// fields into them.
+#[async_trait]
+pub trait TorrentBackend: Send + Sync + 'static {
+    /// Open session and prepare torrent client for the bot
+    async fn connect(&self) -> Result<()>;
+
+    /// Client and API versions for diagnostics, e.g. `qBittorrent v4.6.0, WebAPI 2.9`
+    async fn version(&self) -> Result<String>;
+
+    async fn list(&self) -> Result<Vec<TorrentFields>>;
+
+    /// Add torrent by magnet or http link, `owner` is the user who added it
+    async fn add(&self, link: &str, owner: Option<&str>) -> Result<()>;
+
+    async fn pause(&self, hash: &str) -> Result<()>;
+
+    async fn resume(&self, hash: &str) -> Result<()>;
+
+    /// Set speed limits in bytes per second, `None` keeps current limit and zero removes it
+    async fn set_limits(&self, hash: &str, dl: Option<u64>, up: Option<u64>) -> Result<()>;
+
+    /// Check torrent `state` field for paused torrent
+    fn is_paused(&self, state: &str) -> bool {
+        state.starts_with("paused")
+    }
+
+    async fn delete(&self, hash: &str, delete_files: bool) -> Result<()>;
+
+    /// Torrent properties in Qbittorrent /torrents/properties format
+    async fn properties(&self, hash: &str) -> Result<Value>;
+
+    /// Apply changes since the previous sync to the state
+    async fn sync(&self, state: &mut SyncState) -> Result<()>;
+}
+
+pub fn from_config(config: &InstanceConfig) -> Box<dyn TorrentBackend> {
+    match config.backend {
+        BackendKind::Qbittorrent => Box::new(QbittorrentBackend::new(config)),
+        BackendKind::Transmission => Box::new(TransmissionBackend::new(config)),
+    }
+}
+
+#[derive(Debug)]
+pub enum QbError {
+    /// Torrent client rejected credentials or the session
+    Auth(String),
+    /// Torrent client answered with non-success status code
+    Http(StatusCode),
+    /// Torrent client didn't answer in time
+    Timeout,
+    /// Torrent client is not reachable
+    Connection(reqwest::Error),
+    /// Torrent client response can't be parsed
+    BadJson(String),
+    /// Torrent client understood the request but refused to do it
+    Rejected(String),
+}
+
+impl QbError {
+    /// Errors after which torrent client has to be reconnected
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, Self::Auth(_) | Self::Timeout | Self::Connection(_))
+    }
+}
+
+impl Display for QbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auth(reason) => write!(f, "Torrent client authentication failed: {}", reason),
+            Self::Http(status) => {
+                write!(f, "Torrent client request failed. Status code: {}", status)
+            }
+            Self::Timeout => write!(f, "Torrent client request timed out"),
+            Self::Connection(err) => write!(f, "Failed to connect to torrent client: {}", err),
+            Self::BadJson(err) => write!(f, "Failed to parse torrent client response: {}", err),
+            Self::Rejected(reason) => write!(f, "Torrent client rejected request: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for QbError {}
+
+impl From<reqwest::Error> for QbError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.is_decode() {
+            Self::BadJson(err.to_string())
+        } else {
+            Self::Connection(err)
+        }
+    }
+}
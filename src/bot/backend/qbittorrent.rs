@@ -0,0 +1,334 @@
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, ORIGIN},
+    Client, Response, StatusCode,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::bot::{
+    commands::cmd_list::{
+        Login, QDelete, QDownload, QEmpty, QGetProperties, QLimit, QMaindata, QPause, QResume,
+        QTag, QbList,
+    },
+    config::InstanceConfig,
+    health, metrics, owner_tag,
+    sync::{SyncState, TorrentFields},
+    TAG_NAME,
+};
+
+use super::{QbError, TorrentBackend};
+
+/// WebAPI version of Qbittorrent 5.0, which renamed pause/resume into stop/start
+const STOP_START_API: (u32, u32) = (2, 11);
+
+/// Qbittorrent WebUI API client
+#[derive(Debug)]
+pub struct QbittorrentBackend {
+    config: InstanceConfig,
+    client: Client,
+    /// (major, minor) WebAPI version detected on connect
+    api_version: RwLock<(u32, u32)>,
+}
+
+impl QbittorrentBackend {
+    pub fn new(config: &InstanceConfig) -> Self {
+        let headers = Self::gen_headers(config.location.clone());
+        Self {
+            client: Self::build_client(headers),
+            config: config.to_owned(),
+            api_version: RwLock::new((2, 0)),
+        }
+    }
+
+    fn gen_headers(origin: String) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, origin.parse().unwrap());
+        headers
+    }
+
+    fn build_client(headers: HeaderMap) -> Client {
+        Client::builder()
+            .cookie_store(true)
+            .default_headers(headers)
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap()
+    }
+
+    async fn send_post<T: Serialize>(
+        &self,
+        location: &str,
+        action: &T,
+    ) -> Result<Response, QbError> {
+        let mut api_loc = self.config.location.clone();
+        api_loc.push_str("/api/v2");
+        api_loc.push_str(location);
+        let resp = self.client.post(api_loc).form(action).send().await?;
+        trace!("{:#?}", resp);
+        Ok(resp)
+    }
+
+    fn check_status(resp: Response) -> Result<Response, QbError> {
+        match resp.status() {
+            status if status.is_success() => Ok(resp),
+            StatusCode::FORBIDDEN => Err(QbError::Auth(String::from("Forbidden"))),
+            status => Err(QbError::Http(status)),
+        }
+    }
+
+    /// Send request to Qbittorrent API. Expired session is renewed once and the request is replayed.
+    pub async fn qpost<T: Serialize>(
+        &self,
+        location: &str,
+        action: T,
+    ) -> Result<Response, QbError> {
+        let started = Instant::now();
+        let res = self.post_with_login(location, action).await;
+        metrics::observe_request(location, started.elapsed(), res.is_ok());
+        health::record_request(&self.config.name, &res);
+        res
+    }
+
+    /// Log in again once if the session is expired
+    async fn post_with_login<T: Serialize>(
+        &self,
+        location: &str,
+        action: T,
+    ) -> Result<Response, QbError> {
+        let resp = self.send_post(location, &action).await?;
+        if resp.status() == StatusCode::FORBIDDEN {
+            info!("Qbittorrent session expired. Trying to re-login");
+            self.login().await?;
+            let resp = self.send_post(location, &action).await?;
+            return Self::check_status(resp);
+        }
+        Self::check_status(resp)
+    }
+
+    pub async fn qpost_json<T: Serialize, R: DeserializeOwned>(
+        &self,
+        location: &str,
+        action: T,
+    ) -> Result<R, QbError> {
+        let body = self.qpost(location, action).await?.text().await?;
+        serde_json::from_str(&body).map_err(|err| QbError::BadJson(err.to_string()))
+    }
+
+    /// Log in and detect the WebAPI version, the client may be upgraded while the bot runs
+    pub async fn login(&self) -> Result<(), QbError> {
+        let res = self.send_login().await;
+        health::record_request(&self.config.name, &res);
+        res?;
+        self.detect_api_version().await
+    }
+
+    async fn send_login(&self) -> Result<(), QbError> {
+        let login = Login {
+            username: self.config.user.clone(),
+            password: self.config.password.clone(),
+        };
+        let resp = Self::check_status(self.send_post("/auth/login", &login).await?)?;
+        // Qbittorrent answers 200 even for wrong credentials, so the body has to be checked
+        let body = resp.text().await?;
+        if body.trim() == "Ok." {
+            Ok(())
+        } else {
+            Err(QbError::Auth(format!(
+                "credentials rejected ({})",
+                body.trim()
+            )))
+        }
+    }
+
+    /// Sent without [Self::qpost], which would log in again on 403
+    async fn detect_api_version(&self) -> Result<(), QbError> {
+        let resp = self.send_post("/app/webapiVersion", &QEmpty {}).await?;
+        let version = Self::check_status(resp)?.text().await?;
+        let mut parts = version.trim().split('.').map(|part| part.parse::<u32>());
+        let api_version = match (parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) => (major, minor),
+            _ => return Err(QbError::BadJson(format!("bad WebAPI version {}", version))),
+        };
+        info!("{} WebAPI version is {}", self.config.name, version.trim());
+        *self.api_version.write().unwrap() = api_version;
+        Ok(())
+    }
+
+    pub fn api_version(&self) -> (u32, u32) {
+        *self.api_version.read().unwrap()
+    }
+
+    fn has_stop_start(&self) -> bool {
+        self.api_version() >= STOP_START_API
+    }
+
+    async fn create_tag(&self) -> Result<()> {
+        self.qpost(
+            "/torrents/createTags",
+            QTag {
+                tags: TAG_NAME.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TorrentBackend for QbittorrentBackend {
+    async fn connect(&self) -> Result<()> {
+        self.login().await?;
+        if self.create_tag().await.is_err() {
+            error!("Failed to create tag for Qbitbot. Probably your qbittorrent version doesn't support it")
+        };
+        Ok(())
+    }
+
+    async fn version(&self) -> Result<String> {
+        let version = self
+            .qpost("/app/version", QEmpty {})
+            .await?
+            .text()
+            .await
+            .map_err(QbError::from)?;
+        let (major, minor) = self.api_version();
+        Ok(format!(
+            "qBittorrent {}, WebAPI {}.{}",
+            version.trim(),
+            major,
+            minor
+        ))
+    }
+
+    async fn list(&self) -> Result<Vec<TorrentFields>> {
+        let list = self
+            .qpost_json(
+                "/torrents/info",
+                QbList {
+                    sort: "hash".to_string(),
+                },
+            )
+            .await?;
+        Ok(list)
+    }
+
+    async fn add(&self, link: &str, owner: Option<&str>) -> Result<()> {
+        let mut tags = vec![TAG_NAME.to_string()];
+        tags.extend(owner.map(owner_tag));
+        let body = self
+            .qpost(
+                "/torrents/add",
+                QDownload {
+                    urls: link.to_string(),
+                    tags: tags.join(","),
+                },
+            )
+            .await?
+            .text()
+            .await
+            .map_err(QbError::from)?;
+        if body.trim() == "Fails." {
+            Err(QbError::Rejected(String::from("torrent was not added")).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn pause(&self, hash: &str) -> Result<()> {
+        let location = if self.has_stop_start() {
+            "/torrents/stop"
+        } else {
+            "/torrents/pause"
+        };
+        self.qpost(
+            location,
+            QPause {
+                hashes: hash.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn resume(&self, hash: &str) -> Result<()> {
+        let location = if self.has_stop_start() {
+            "/torrents/start"
+        } else {
+            "/torrents/resume"
+        };
+        self.qpost(
+            location,
+            QResume {
+                hashes: hash.to_string(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn set_limits(&self, hash: &str, dl: Option<u64>, up: Option<u64>) -> Result<()> {
+        let limits = [
+            ("/torrents/setDownloadLimit", dl),
+            ("/torrents/setUploadLimit", up),
+        ];
+        for (location, limit) in limits.iter() {
+            if let Some(limit) = *limit {
+                self.qpost(
+                    location,
+                    QLimit {
+                        hashes: hash.to_string(),
+                        limit,
+                    },
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_paused(&self, state: &str) -> bool {
+        if self.has_stop_start() {
+            state.starts_with("stopped")
+        } else {
+            state.starts_with("paused")
+        }
+    }
+
+    async fn delete(&self, hash: &str, delete_files: bool) -> Result<()> {
+        self.qpost(
+            "/torrents/delete",
+            QDelete {
+                hashes: hash.to_string(),
+                delete_files,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn properties(&self, hash: &str) -> Result<Value> {
+        let props = self
+            .qpost_json(
+                "/torrents/properties",
+                QGetProperties {
+                    hash: hash.to_string(),
+                },
+            )
+            .await?;
+        Ok(props)
+    }
+
+    async fn sync(&self, state: &mut SyncState) -> Result<()> {
+        let delta: Value = self
+            .qpost_json("/sync/maindata", QMaindata { rid: state.rid() })
+            .await?;
+        state.apply(&delta)
+    }
+}
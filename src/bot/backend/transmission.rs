@@ -0,0 +1,308 @@
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::{Client, Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+use crate::bot::{
+    config::InstanceConfig,
+    health, metrics, owner_tag,
+    sync::{SyncState, TorrentFields},
+    TAG_NAME,
+};
+
+use super::{QbError, TorrentBackend};
+
+const SESSION_HEADER: &str = "X-Transmission-Session-Id";
+
+const LIST_FIELDS: &[&str] = &[
+    "hashString",
+    "name",
+    "totalSize",
+    "percentDone",
+    "eta",
+    "doneDate",
+    "status",
+    "uploadRatio",
+    "rateDownload",
+    "rateUpload",
+    "labels",
+];
+
+const PROPERTIES_FIELDS: &[&str] = &[
+    "hashString",
+    "downloadDir",
+    "totalSize",
+    "addedDate",
+    "doneDate",
+    "uploadRatio",
+    "rateDownload",
+    "rateUpload",
+    "peersConnected",
+];
+
+/// Qbittorrent eta value for unknown eta
+const INFINITE_ETA: i64 = 8640000;
+
+/// Transmission RPC client
+#[derive(Debug)]
+pub struct TransmissionBackend {
+    config: InstanceConfig,
+    client: Client,
+    session_id: RwLock<Option<String>>,
+}
+
+impl TransmissionBackend {
+    pub fn new(config: &InstanceConfig) -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap(),
+            config: config.to_owned(),
+            session_id: RwLock::new(None),
+        }
+    }
+
+    async fn send(&self, body: &Value) -> Result<Response, QbError> {
+        let mut request = self
+            .client
+            .post(format!("{}/transmission/rpc", self.config.location))
+            .json(body);
+        if !self.config.user.is_empty() {
+            request = request.basic_auth(&self.config.user, Some(&self.config.password));
+        }
+        let session_id = self.session_id.read().unwrap().clone();
+        if let Some(session_id) = session_id {
+            request = request.header(SESSION_HEADER, session_id);
+        }
+        let resp = request.send().await?;
+        trace!("{:#?}", resp);
+        Ok(resp)
+    }
+
+    /// Call RPC method. Transmission answers 409 with a new session id when the
+    /// current one is missing or outdated, then the request is replayed with it.
+    pub async fn rpc(&self, method: &str, arguments: Value) -> Result<Value, QbError> {
+        let started = Instant::now();
+        let res = self.call(method, arguments).await;
+        metrics::observe_request(method, started.elapsed(), res.is_ok());
+        health::record_request(&self.config.name, &res);
+        res
+    }
+
+    async fn call(&self, method: &str, arguments: Value) -> Result<Value, QbError> {
+        let body = json!({"method": method, "arguments": arguments});
+        let mut resp = self.send(&body).await?;
+        if resp.status() == StatusCode::CONFLICT {
+            let session_id = resp
+                .headers()
+                .get(SESSION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+                .ok_or_else(|| QbError::Auth(String::from("no session id in 409 response")))?;
+            *self.session_id.write().unwrap() = Some(session_id);
+            resp = self.send(&body).await?;
+        }
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::CONFLICT => {
+                return Err(QbError::Auth(resp.status().to_string()))
+            }
+            status if !status.is_success() => return Err(QbError::Http(status)),
+            _ => (),
+        }
+        let resp: Value = serde_json::from_str(&resp.text().await?)
+            .map_err(|err| QbError::BadJson(err.to_string()))?;
+        match resp.get("result").and_then(Value::as_str) {
+            Some("success") => Ok(resp.get("arguments").cloned().unwrap_or(Value::Null)),
+            Some(result) => Err(QbError::Rejected(result.to_string())),
+            None => Err(QbError::BadJson(String::from("no result in response"))),
+        }
+    }
+
+    async fn get_torrents(&self, fields: &[&str], hash: Option<&str>) -> Result<Vec<Value>> {
+        let mut arguments = json!({ "fields": fields });
+        if let Some(hash) = hash {
+            arguments["ids"] = json!([hash]);
+        }
+        let resp = self.rpc("torrent-get", arguments).await?;
+        let torrents = resp
+            .get("torrents")
+            .and_then(Value::as_array)
+            .cloned()
+            .ok_or_else(|| QbError::BadJson(String::from("no torrents in response")))?;
+        Ok(torrents)
+    }
+
+    /// Translate Transmission torrent into Qbittorrent fields
+    fn to_qb_fields(torrent: &Value) -> Option<TorrentFields> {
+        let progress = torrent.get("percentDone")?.as_f64()?;
+        let eta = match torrent.get("eta")?.as_i64()? {
+            eta if eta < 0 => INFINITE_ETA,
+            eta => eta,
+        };
+        let completion_on = match torrent.get("doneDate")?.as_i64()? {
+            0 => -1,
+            date => date,
+        };
+        let state = match torrent.get("status")?.as_i64()? {
+            0 if progress >= 1.0 => "pausedUP",
+            0 => "pausedDL",
+            1 | 2 => "checkingDL",
+            3 => "queuedDL",
+            4 => "downloading",
+            5 => "queuedUP",
+            _ => "uploading",
+        };
+        let mut fields = Map::new();
+        fields.insert(String::from("hash"), torrent.get("hashString")?.clone());
+        fields.insert(String::from("name"), torrent.get("name")?.clone());
+        fields.insert(String::from("size"), torrent.get("totalSize")?.clone());
+        fields.insert(String::from("progress"), json!(progress));
+        fields.insert(String::from("eta"), json!(eta));
+        fields.insert(String::from("completion_on"), json!(completion_on));
+        fields.insert(String::from("state"), json!(state));
+        if let Some(ratio) = torrent.get("uploadRatio").and_then(Value::as_f64) {
+            fields.insert(String::from("ratio"), json!(ratio.max(0.0)));
+        }
+        if let Some(labels) = torrent.get("labels").and_then(Value::as_array) {
+            let labels: Vec<&str> = labels.iter().filter_map(Value::as_str).collect();
+            fields.insert(String::from("tags"), json!(labels.join(", ")));
+        }
+        for (from, to) in [("rateDownload", "dlspeed"), ("rateUpload", "upspeed")].iter() {
+            if let Some(speed) = torrent.get(*from) {
+                fields.insert(to.to_string(), speed.clone());
+            }
+        }
+        Some(fields)
+    }
+
+    async fn call_for_hash(&self, method: &str, hash: &str) -> Result<()> {
+        self.rpc(method, json!({ "ids": [hash] })).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TorrentBackend for TransmissionBackend {
+    async fn connect(&self) -> Result<()> {
+        self.rpc("session-get", json!({})).await?;
+        Ok(())
+    }
+
+    async fn version(&self) -> Result<String> {
+        let session = self
+            .rpc("session-get", json!({"fields": ["version", "rpc-version"]}))
+            .await?;
+        let version = session["version"].as_str().unwrap_or("unknown");
+        Ok(format!(
+            "Transmission {}, RPC {}",
+            version, session["rpc-version"]
+        ))
+    }
+
+    async fn list(&self) -> Result<Vec<TorrentFields>> {
+        let torrents = self.get_torrents(LIST_FIELDS, None).await?;
+        Ok(torrents.iter().filter_map(Self::to_qb_fields).collect())
+    }
+
+    async fn add(&self, link: &str, owner: Option<&str>) -> Result<()> {
+        let mut labels = vec![TAG_NAME.to_string()];
+        labels.extend(owner.map(owner_tag));
+        let resp = self
+            .rpc("torrent-add", json!({ "filename": link, "labels": labels }))
+            .await?;
+        if resp.get("torrent-duplicate").is_some() {
+            Err(QbError::Rejected(String::from("torrent is already added")).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn pause(&self, hash: &str) -> Result<()> {
+        self.call_for_hash("torrent-stop", hash).await
+    }
+
+    async fn resume(&self, hash: &str) -> Result<()> {
+        self.call_for_hash("torrent-start", hash).await
+    }
+
+    /// Transmission limits are in KB/s and switched on separately
+    async fn set_limits(&self, hash: &str, dl: Option<u64>, up: Option<u64>) -> Result<()> {
+        let mut arguments = json!({ "ids": [hash] });
+        if let Some(dl) = dl {
+            arguments["downloadLimit"] = json!(dl / 1024);
+            arguments["downloadLimited"] = json!(dl > 0);
+        }
+        if let Some(up) = up {
+            arguments["uploadLimit"] = json!(up / 1024);
+            arguments["uploadLimited"] = json!(up > 0);
+        }
+        self.rpc("torrent-set", arguments).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, hash: &str, delete_files: bool) -> Result<()> {
+        self.rpc(
+            "torrent-remove",
+            json!({ "ids": [hash], "delete-local-data": delete_files }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn properties(&self, hash: &str) -> Result<Value> {
+        let torrents = self.get_torrents(PROPERTIES_FIELDS, Some(hash)).await?;
+        let torrent = torrents
+            .first()
+            .ok_or_else(|| anyhow!("There is no torrent with hash {}", hash))?;
+        let completion_date = match torrent.get("doneDate").and_then(Value::as_i64) {
+            Some(0) | None => -1,
+            Some(date) => date,
+        };
+        Ok(json!({
+            "save_path": torrent.get("downloadDir"),
+            "total_size": torrent.get("totalSize"),
+            "addition_date": torrent.get("addedDate"),
+            "completion_date": completion_date,
+            "share_ratio": torrent.get("uploadRatio"),
+            "dl_speed": torrent.get("rateDownload"),
+            "up_speed": torrent.get("rateUpload"),
+            "peers": torrent.get("peersConnected"),
+        }))
+    }
+
+    /// Transmission has no deltas, so every sync is a full update
+    async fn sync(&self, state: &mut SyncState) -> Result<()> {
+        let torrents: Map<String, Value> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter_map(|fields| {
+                let hash = fields.get("hash")?.as_str()?.to_string();
+                Some((hash, Value::Object(fields)))
+            })
+            .collect();
+        let speed = |field: &str| -> u64 {
+            torrents
+                .values()
+                .filter_map(|fields| fields.get(field).and_then(Value::as_u64))
+                .sum()
+        };
+        let server_state = json!({
+            "dl_info_speed": speed("dlspeed"),
+            "up_info_speed": speed("upspeed"),
+        });
+        let rid = state.rid() + 1;
+        state.apply(&json!({
+            "rid": rid,
+            "full_update": true,
+            "torrents": torrents,
+            "server_state": server_state,
+        }))
+    }
+}
@@ -0,0 +1,199 @@
+//! Command line of the binary: the bot itself and one-shot commands for scripting,
+//! which share the torrent client logic with the bot.
+use anyhow::{anyhow, Result};
+
+use crate::bot::audit::{Actor, AuditEntry, AuditLog};
+use crate::bot::backend;
+use crate::bot::commands::download::QDownloadAction;
+use crate::bot::commands::QbCommandAction;
+use crate::bot::config::{InstanceConfig, QbConfig};
+use crate::bot::html;
+use crate::bot::qb_client::QbClient;
+
+pub const USAGE: &str = "Usage: qbitbot [--config <path>] [command]
+
+Commands:
+  run                                Start the bot, the default
+  check-config                       Validate the config and exit
+  test-connection [--instance <name>] Log in to every instance and show its version
+  list [--instance <name>]           Print torrents
+  add <link> [--instance <name>] [--user <name>]
+                                     Add torrent by magnet or http link, owned by the
+                                     user for /mine. $USER is the default owner
+  repl [--user <name>]               Chat with the bot in the terminal, the token is not
+                                     needed. The first admin is the default user.
+                                     Changes are audited to <audit_file>.repl.jsonl
+
+Without --config qbitbot.toml or .env of the working dir is used.";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    CheckConfig,
+    TestConnection {
+        instance: Option<String>,
+    },
+    List {
+        instance: Option<String>,
+    },
+    Add {
+        link: String,
+        instance: Option<String>,
+        user: Option<String>,
+    },
+    Repl {
+        user: Option<String>,
+    },
+    Help,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cli {
+    pub config: Option<String>,
+    pub command: Command,
+}
+
+impl Cli {
+    /// Parse arguments without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = None;
+        let mut instance = None;
+        let mut user = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if let Some(path) = arg.strip_prefix("--config=") {
+                config = Some(path.to_string());
+                continue;
+            }
+            let mut value =
+                |name: &str| args.next().ok_or_else(|| anyhow!("{} needs a value", name));
+            match arg.as_str() {
+                "--config" => config = Some(value("--config")?),
+                "--instance" => instance = Some(value("--instance")?),
+                "--user" => user = Some(value("--user")?),
+                "-h" | "--help" => positional.insert(0, String::from("help")),
+                // used before the subcommands were added
+                "--check-config" => positional.insert(0, String::from("check-config")),
+                _ if arg.starts_with('-') => return Err(anyhow!("Unknown option {}", arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let name = positional.next();
+        let command_name = name.as_deref().unwrap_or("run");
+        // options of other commands are refused rather than ignored
+        if instance.is_some() && !["test-connection", "list", "add"].contains(&command_name) {
+            return Err(anyhow!("{} doesn't take --instance", command_name));
+        }
+        if user.is_some() && !["add", "repl"].contains(&command_name) {
+            return Err(anyhow!("{} doesn't take --user", command_name));
+        }
+        let command = match name.as_deref() {
+            None | Some("run") => Command::Run,
+            Some("check-config") => Command::CheckConfig,
+            Some("test-connection") => Command::TestConnection { instance },
+            Some("list") => Command::List { instance },
+            Some("add") => Command::Add {
+                link: positional
+                    .next()
+                    .ok_or_else(|| anyhow!("add needs a link"))?,
+                instance,
+                user,
+            },
+            Some("repl") => Command::Repl { user },
+            Some("help") => Command::Help,
+            Some(other) => return Err(anyhow!("Unknown command {}", other)),
+        };
+        if let Some(extra) = positional.next() {
+            return Err(anyhow!("Unexpected argument {}", extra));
+        }
+        Ok(Self { config, command })
+    }
+}
+
+/// Instance by name, the first one by default like in the bot
+fn find_instance<'a>(config: &'a QbConfig, name: Option<&str>) -> Result<&'a InstanceConfig> {
+    match name {
+        Some(name) => config
+            .instances
+            .iter()
+            .find(|instance| instance.name == name)
+            .ok_or_else(|| anyhow!("There is no instance {}", name)),
+        None => Ok(&config.instances[0]),
+    }
+}
+
+/// Log in to every instance or only the named one, a line per instance with its version
+/// or error. Returns false if some of them failed.
+pub async fn test_connection(config: &QbConfig, instance: Option<&str>) -> Result<(bool, String)> {
+    let instances = match instance {
+        Some(_) => vec![find_instance(config, instance)?],
+        None => config.instances.iter().collect(),
+    };
+    let mut all_ok = true;
+    let mut lines = Vec::new();
+    for instance in instances {
+        let backend = backend::from_config(instance);
+        let res = match backend.connect().await {
+            Ok(()) => backend.version().await,
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(version) => lines.push(format!("{}: OK, {}", instance.name, version)),
+            Err(err) => {
+                all_ok = false;
+                lines.push(format!("{}: FAILED, {:#}", instance.name, err));
+            }
+        }
+    }
+    Ok((all_ok, lines.join("\n")))
+}
+
+/// Torrents of the instance as the bot lists them, without markup
+pub async fn list(config: &QbConfig, instance: Option<&str>) -> Result<String> {
+    let client = QbClient::new(find_instance(config, instance)?).await;
+    let list = client.get_cached_list().await?;
+    Ok(html::strip(&list.action_result_to_string()))
+}
+
+/// Add torrent like the download menu of the bot, prints OK or FAIL.
+/// The torrent is tagged with `user` and audited like the bot does.
+pub async fn add(
+    config: &QbConfig,
+    link: &str,
+    instance: Option<&str>,
+    user: Option<&str>,
+) -> Result<String> {
+    let client = QbClient::new(find_instance(config, instance)?).await;
+    let owner = match user {
+        Some(user) => user.to_string(),
+        None => std::env::var("USER").unwrap_or_else(|_| String::from("cli")),
+    };
+    let actor = Actor {
+        user_id: None,
+        username: owner.clone(),
+        chat_id: None,
+    };
+    let audit = AuditLog::new(&config.audit_file);
+    let entry = AuditEntry::new(&actor, "download", &client)
+        .param("link", link)
+        .param("source", "cli");
+    let action = match QDownloadAction::default()
+        .send_link(&client, link, Some(&owner))
+        .await
+    {
+        Ok(action) => action,
+        Err(err) => {
+            audit.record(entry.outcome(format!("{:#}", err)));
+            return Err(err);
+        }
+    };
+    let entry = match action.hash() {
+        Some(hash) => entry.target(hash, QDownloadAction::get_name(&client, hash).await),
+        None => entry,
+    };
+    audit.record(entry.outcome(action.action_result_to_string()));
+    Ok(action.action_result_to_string())
+}
@@ -6,6 +6,9 @@ pub struct Login {
     pub password: String,
 }
 
+#[derive(Serialize)]
+pub struct QEmpty {}
+
 #[derive(Serialize)]
 pub struct QTag {
     pub tags: String,
@@ -37,13 +40,20 @@ pub struct QResume {
     pub hashes: String,
 }
 
-#[derive(Serialize, Debug, Clone)]
-pub struct MaindataResponse {
+#[derive(Serialize)]
+pub struct QLimit {
+    pub hashes: String,
+    pub limit: u64,
+}
+
+#[derive(Serialize)]
+pub struct QMaindata {
     pub rid: i64,
 }
 
-impl Default for MaindataResponse {
-    fn default() -> Self {
-        Self { rid: 0 }
-    }
+#[derive(Serialize)]
+pub struct QDelete {
+    pub hashes: String,
+    #[serde(rename = "deleteFiles")]
+    pub delete_files: bool,
 }
@@ -1,17 +1,26 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
 use fure::backoff::fixed;
 use fure::policies::{attempts, backoff};
-use reqwest::Response;
 use tokio::sync::oneshot::Sender;
 use tokio::time::{sleep, Duration};
 
+use crate::bot::backend::QbError;
 use crate::bot::notifier::CheckType;
 use crate::bot::notifier::CheckType::Completed;
-use crate::bot::{qb_client::QbClient, TAG_NAME};
+use crate::bot::qb_client::QbClient;
+use crate::bot::watchers::{Watch, Watchers};
 
-use super::{cmd_list::QDownload, list::QListAction, QbCommandAction};
+use super::QbCommandAction;
+
+#[derive(PartialEq)]
+enum Progress {
+    Done,
+    /// Deleted from the client
+    Gone,
+}
 
 #[derive(Default)]
 pub struct QDownloadAction {
@@ -23,15 +32,15 @@ impl QDownloadAction {
     async fn check_added(
         &mut self,
         client: &QbClient,
-        list_before: Option<HashSet<String>>,
+        list_before: Option<BTreeSet<String>>,
     ) -> Result<()> {
         if let Some(hashes) = list_before {
             let closure = || async {
                 let list_after = Self::get_hashes(client).await.unwrap_or_default();
-                let diff: HashSet<String> = list_after.difference(&hashes).cloned().collect();
+                let diff: BTreeSet<String> = list_after.difference(&hashes).cloned().collect();
                 // TODO: fix this barely good check
                 if diff.len() == 1 {
-                    Ok(diff.iter().cloned().next().unwrap())
+                    Ok(diff.into_iter().next().unwrap())
                 } else {
                     Err(())
                 }
@@ -49,35 +58,15 @@ impl QDownloadAction {
         }
     }
 
-    async fn inner_send(client: &QbClient, link: &str) -> Result<Response> {
-        let resp = client
-            .qpost(
-                "/torrents/add",
-                QDownload {
-                    urls: link.to_string(),
-                    tags: TAG_NAME.to_string(),
-                },
-            )
-            .await?;
-        Ok(resp)
-    }
-
-    async fn get_hashes(client: &QbClient) -> Option<HashSet<String>> {
-        let hashes = QListAction::get(client)
-            .await
-            .ok()?
-            .as_array()?
-            .iter()
-            .filter_map(|item| serde_json::from_value(item.get("hash").cloned()?).ok())
-            .collect();
-        Some(hashes)
+    async fn get_hashes(client: &QbClient) -> Option<BTreeSet<String>> {
+        Some(client.sync().await.ok()?.hashes())
     }
 
-    pub async fn get_name(client: &mut QbClient, hash: &str) -> Option<String> {
+    pub async fn get_name(client: &QbClient, hash: &str) -> Option<String> {
         client
             .get_cached_list()
             .await
-            .unwrap()
+            .ok()?
             .get_records()
             .iter()
             .find_map(|item| {
@@ -89,56 +78,109 @@ impl QDownloadAction {
             })
     }
 
-    async fn check_is_completed(client: &QbClient, hash: &str, name: &str) -> Result<String> {
-        let res = if let Ok(props) = QListAction::get_properties(client, hash.to_string()).await {
-            let completion_date_opt = move || -> Option<i64> {
-                let obj = props.as_object()?;
-                obj.get("completion_date")?.as_i64()
-            }();
-            if let Some(completion_date) = completion_date_opt {
-                if completion_date != -1 {
-                    Ok(format!("{} is done", name))
-                } else {
-                    Err(anyhow!("Torrent in progress"))
-                }
-            } else {
-                Err(anyhow!("Failed to get torrent status"))
+    /// `None` while the torrent is in progress or the client can't be reached
+    async fn check_is_completed(client: &QbClient, hash: &str, name: &str) -> Option<Progress> {
+        let state = match client.sync().await {
+            Ok(state) => state,
+            Err(err) => {
+                debug!("Failed to check {}: {:#}", name, err);
+                return None;
             }
-        } else {
-            Err(anyhow!("Failed to get torrent status"))
         };
         debug!("Checked {} for completion", name);
-        res
+        match state.torrent(hash) {
+            None => Some(Progress::Gone),
+            Some(torrent) => match torrent.get("progress").and_then(|value| value.as_f64()) {
+                Some(progress) if progress >= 1.0 => Some(Progress::Done),
+                _ => None,
+            },
+        }
     }
 
-    pub async fn create_notifier(&self, client: &mut QbClient, tx: Sender<CheckType>) {
+    /// Watch the added torrent. `instance` is shown in the notification if it's set
+    pub async fn create_notifier(
+        &self,
+        client: &Arc<QbClient>,
+        instance: Option<String>,
+        chat_id: i64,
+        fallback_chat_id: Option<i64>,
+        watchers: &Arc<Watchers>,
+        tx: Sender<CheckType>,
+    ) {
         let hash = self.torrent_hash.clone();
         if self.status {
             let res = Self::get_name(client, &hash).await;
             if let Some(name) = res {
-                let my_client = client.clone();
-                tokio::spawn(async move {
-                    while Self::check_is_completed(&my_client, &hash, &name)
-                        .await
-                        .is_err()
-                    {
-                        sleep(Duration::from_secs(1)).await;
-                    }
-                    if tx.send(Completed(name)).is_err() {
-                        error!("Failed to send 'completed' status into channel")
-                    }
-                });
+                let watch = Watch {
+                    chat_id,
+                    fallback_chat_id,
+                    instance: client.name().to_string(),
+                    hash,
+                    name,
+                };
+                Self::spawn_watch(client.clone(), watch, instance, watchers.clone(), tx);
             } else {
                 error!("Failed to get torrent name");
             }
         }
     }
 
-    pub async fn send_link(mut self, client: &QbClient, link: &str) -> Result<Self> {
+    /// Send `Completed` into `tx` when the torrent is done. The watch is kept in `watchers`
+    /// until then, so it can be restored after restart. It ends without a notification
+    /// if the torrent is deleted.
+    pub fn spawn_watch(
+        client: Arc<QbClient>,
+        watch: Watch,
+        instance: Option<String>,
+        watchers: Arc<Watchers>,
+        tx: Sender<CheckType>,
+    ) {
+        watchers.add(watch.clone());
+        tokio::spawn(async move {
+            let progress = loop {
+                match Self::check_is_completed(&client, &watch.hash, &watch.name).await {
+                    Some(progress) => break progress,
+                    None => sleep(watchers.settings().check_interval).await,
+                }
+            };
+            watchers.remove(&watch);
+            if progress == Progress::Gone {
+                info!("{} is deleted, it's not watched anymore", watch.name);
+                return;
+            }
+            if !watchers.settings().completed {
+                debug!("Notifications are off, {} is done", watch.name);
+                return;
+            }
+            let name = watch.name;
+            if tx.send(Completed { name, instance }).is_err() {
+                error!("Failed to send 'completed' status into channel")
+            }
+        });
+    }
+
+    /// Hash of the added torrent, if it was added
+    pub fn hash(&self) -> Option<&str> {
+        Some(self.torrent_hash.as_str()).filter(|_| self.status)
+    }
+
+    /// Add the torrent tagged with `owner`, see [owner_tag](crate::bot::owner_tag)
+    pub async fn send_link(
+        mut self,
+        client: &QbClient,
+        link: &str,
+        owner: Option<&str>,
+    ) -> Result<Self> {
         let list_before = Self::get_hashes(client).await;
-        let send_resp = Self::inner_send(client, link).await?;
-        self.status =
-            send_resp.status().is_success() && self.check_added(client, list_before).await.is_ok();
+        match client.backend().add(link, owner).await {
+            Ok(()) => self.status = self.check_added(client, list_before).await.is_ok(),
+            // e.g. torrent is already added
+            Err(err) if matches!(err.downcast_ref::<QbError>(), Some(QbError::Rejected(_))) => {
+                info!("Torrent was not added: {:#}", err);
+                self.status = false
+            }
+            Err(err) => return Err(err),
+        }
         Ok(self)
     }
 }
@@ -0,0 +1,63 @@
+use anyhow::{anyhow, Result};
+
+use crate::bot::qb_client::QbClient;
+
+use super::QbCommandAction;
+
+/// Parse speed like `512K`, `2M` or `100` into bytes per second
+///
+/// Example:
+/// ```
+/// # use qbitbot::bot::commands::limit::parse_speed;
+/// assert_eq!(parse_speed("2M"), Some(2 * 1024 * 1024));
+/// assert_eq!(parse_speed("fast"), None);
+/// ```
+pub fn parse_speed(text: &str) -> Option<u64> {
+    let text = text.trim().to_uppercase();
+    let (number, multiplier) = match text.chars().last()? {
+        'K' => (&text[..text.len() - 1], 1024),
+        'M' => (&text[..text.len() - 1], 1024 * 1024),
+        'G' => (&text[..text.len() - 1], 1024 * 1024 * 1024),
+        _ => (text.as_str(), 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+pub struct QLimitAction {
+    status: Result<()>,
+}
+
+impl QLimitAction {
+    /// `None` keeps current limit, zero removes it
+    pub async fn act_hash(client: &QbClient, hash: &str, dl: Option<u64>, up: Option<u64>) -> Self {
+        Self {
+            status: Self::set_limits(client, hash, dl, up).await,
+        }
+    }
+
+    async fn set_limits(
+        client: &QbClient,
+        hash: &str,
+        dl: Option<u64>,
+        up: Option<u64>,
+    ) -> Result<()> {
+        if dl.is_none() && up.is_none() {
+            return Err(anyhow!("Set dl or up limit"));
+        }
+        client
+            .backend()
+            .set_limits(hash, dl, up)
+            .await
+            .map_err(|err| err.context("Failed to send request to torrent client"))
+    }
+}
+
+impl QbCommandAction for QLimitAction {
+    fn action_result_to_string(&self) -> String {
+        if let Err(error) = &self.status {
+            error.to_string()
+        } else {
+            String::from("OK")
+        }
+    }
+}
@@ -1,108 +1,71 @@
 use std::{
+    cmp::{Ordering, Reverse},
     convert::TryInto,
     fmt::Display,
-    time::{Duration, SystemTime},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Result};
 use chrono::{DateTime, Local};
-use serde_json::{json, Value};
+use serde::Serialize;
+use serde_json::Value;
 
-use crate::bot::commands::cmd_list::QGetProperties;
-use crate::bot::{commands::cmd_list::QbList, qb_client::QbClient};
+use crate::bot::html;
+use crate::bot::sync::{SyncState, TorrentFields};
+use crate::bot::tag_owner;
 
-use super::{cmd_list::MaindataResponse, QbCommandAction};
+use super::QbCommandAction;
 
 #[derive(Debug, Clone)]
 pub struct QListAction {
     records: Vec<QbListRecord>,
-    maindata_response: MaindataResponse,
-    hash: String,
 }
 
 impl QListAction {
-    pub async fn new(client: &QbClient) -> Result<Self> {
-        let records = Self::update_records(client).await?;
-        Ok(QListAction {
-            records,
-            maindata_response: MaindataResponse::default(),
-            hash: String::from(""),
-        })
-    }
-
-    pub async fn get(client: &QbClient) -> Result<Value> {
-        let resp = client
-            .qpost(
-                "/torrents/info",
-                QbList {
-                    sort: "hash".to_string(),
-                },
-            )
-            .await?
-            .json()
-            .await?;
-        Ok(resp)
-    }
-
-    pub async fn get_properties(client: &QbClient, hash: String) -> Result<Value> {
-        let value = client
-            .qpost("/torrents/properties", QGetProperties { hash })
-            .await?
-            .json()
-            .await?;
-        Ok(value)
+    /// Build list from synced state. Torrent ids are positions in hash order.
+    pub fn from_state(state: &SyncState) -> Self {
+        let records = state
+            .torrents()
+            .enumerate()
+            .filter_map(|(num, item)| QbListRecord::parse_record(num, item))
+            .collect();
+        QListAction { records }
     }
 
-    pub async fn check_and_update(&mut self, client: &QbClient) -> Result<()> {
-        match self.check_has_changes(client).await {
-            Err(_) | Ok(true) => {
-                debug!("Need to update cached list");
-                self.records = Self::update_records(client).await?
+    /// Keep torrents matching one of [registry::LIST_FILTERS](super::registry::LIST_FILTERS)
+    /// and sort them by one of [registry::LIST_SORTS](super::registry::LIST_SORTS).
+    /// Torrent ids are not changed.
+    pub fn filter_sort(
+        mut self,
+        filter: Option<&str>,
+        sort: Option<&str>,
+        is_paused: impl Fn(&str) -> bool,
+    ) -> Self {
+        self.records.retain(|record| {
+            let paused = is_paused(&record.state);
+            match filter {
+                Some("downloading") => record.progress < 100 && !paused,
+                Some("seeding") => record.progress >= 100 && !paused,
+                Some("completed") => record.progress >= 100,
+                Some("paused") => paused,
+                _ => true,
             }
-            Ok(false) => (),
-        };
-        Ok(())
-    }
-
-    pub async fn update_records(client: &QbClient) -> Result<Vec<QbListRecord>> {
-        let resp = Self::get(client).await?;
-        if let Some(arr) = resp.as_array() {
-            let records: Vec<QbListRecord> = arr
-                .iter()
-                .enumerate()
-                .filter_map(|(num, item)| QbListRecord::parse_record(num, item))
-                .collect();
-            Ok(records)
-        } else {
-            Err(anyhow!("Failed to get cached list"))
+        });
+        match sort {
+            Some("name") => self.records.sort_by(|a, b| a.name.cmp(&b.name)),
+            Some("size") => self.records.sort_by_key(|record| Reverse(record.size)),
+            Some("progress") => self.records.sort_by_key(|record| Reverse(record.progress)),
+            Some("ratio") => self
+                .records
+                .sort_by(|a, b| b.ratio.partial_cmp(&a.ratio).unwrap_or(Ordering::Equal)),
+            _ => (),
         }
+        self
     }
 
-    async fn check_has_changes(&mut self, client: &QbClient) -> Result<bool> {
-        let resp: Value = client
-            .qpost("/sync/maindata", self.maindata_response.to_owned())
-            .await?
-            .json()
-            .await?;
-
-        self.maindata_response.rid = (|| -> Option<i64> {
-            let res = resp.as_object()?.get("rid")?.as_i64()?;
-            Some(res)
-        })()
-        .ok_or_else(|| anyhow!("Failed to parse Qbittorrent response"))?;
-
-        let updated = (|| -> Option<bool> {
-            let updated = resp
-                .as_object()?
-                .get("full_update")
-                .unwrap_or(&json!("false"))
-                .as_bool()
-                .unwrap_or(false);
-            Some(updated)
-        })()
-        .ok_or_else(|| anyhow!("Failed to parse Qbittorrent response"))?;
-
-        Ok(updated)
+    /// Keep torrents added by `user` with the bot
+    pub fn owned_by(mut self, user: &str) -> Self {
+        self.records.retain(|record| record.is_owned_by(user));
+        self
     }
 
     pub fn get_record_by_num(&self, num: usize) -> Option<QbListRecord> {
@@ -114,29 +77,30 @@ impl QListAction {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct QbListRecord {
+    #[serde(rename = "id")]
     num: usize,
     name: String,
+    #[serde(rename = "size_mb")]
     size: u64,
     progress: u64,
     eta: String,
     hash: String,
+    state: String,
+    ratio: f64,
+    /// User who added the torrent with the bot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
 }
 
 impl QbListRecord {
-    fn parse_name(item: &Value) -> Option<String> {
-        let name = item
-            .get("name")?
-            .to_string()
-            .chars()
-            .filter(|&x| x != '"')
-            .take(20)
-            .collect();
+    fn parse_name(item: &TorrentFields) -> Option<String> {
+        let name = item.get("name")?.as_str()?.chars().take(20).collect();
         Some(name)
     }
 
-    fn parse_eta(item: &Value) -> Option<String> {
+    fn parse_eta(item: &TorrentFields) -> Option<String> {
         let eta = item.get("eta")?.as_i64()?;
         let completion_on = item.get("completion_on")?.as_i64()?;
         let humanized_eta = match (eta, completion_on) {
@@ -152,8 +116,8 @@ impl QbListRecord {
         Some(humanized_eta)
     }
 
-    pub fn parse_record(num: usize, item: &Value) -> Option<Self> {
-        let progress = item.get("progress").unwrap().as_f64()? * 100.0;
+    pub fn parse_record(num: usize, item: &TorrentFields) -> Option<Self> {
+        let progress = item.get("progress")?.as_f64()? * 100.0;
         let record = Self {
             num,
             progress: progress as u64,
@@ -161,6 +125,17 @@ impl QbListRecord {
             size: item.get("size")?.as_u64()? / 1048576,
             eta: Self::parse_eta(item)?,
             hash: item.get("hash")?.as_str()?.to_string(),
+            state: item
+                .get("state")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            ratio: item.get("ratio").and_then(Value::as_f64).unwrap_or(0.0),
+            owner: item
+                .get("tags")
+                .and_then(Value::as_str)
+                .and_then(tag_owner)
+                .map(String::from),
         };
         Some(record)
     }
@@ -172,15 +147,50 @@ impl QbListRecord {
     pub fn get_name(&self) -> String {
         self.name.to_owned()
     }
+
+    /// Lines of the torrent page below the list line, from /torrents/properties fields
+    pub fn details(&self, properties: &Value) -> String {
+        let date = |field: &str| -> Option<String> {
+            let secs = properties.get(field)?.as_i64()?.try_into().ok()?;
+            let time = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(secs));
+            Some(time.format("%Y-%m-%d %H:%M:%S").to_string())
+        };
+        let mut lines = Vec::new();
+        if let Some(path) = properties.get("save_path").and_then(Value::as_str) {
+            lines.push(format!("Save path: {}", html::escape(path)));
+        }
+        if let Some(added) = date("addition_date") {
+            lines.push(format!("Added: {}", added));
+        }
+        if let Some(completed) = date("completion_date") {
+            lines.push(format!("Completed: {}", completed));
+        }
+        lines.push(format!("Ratio: {:.2}", self.ratio));
+        if let Some(peers) = properties.get("peers").and_then(Value::as_u64) {
+            lines.push(format!("Peers: {}", peers));
+        }
+        if let Some(owner) = &self.owner {
+            lines.push(format!("Added by: {}", html::escape(owner)));
+        }
+        lines.join("\n")
+    }
+
+    /// Telegram usernames are case insensitive
+    pub fn is_owned_by(&self, user: &str) -> bool {
+        self.owner
+            .as_deref()
+            .is_some_and(|owner| owner.eq_ignore_ascii_case(user))
+    }
 }
 
 impl Display for QbListRecord {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "/torrent{num}<code> | {name:20} | {size:6} Mb | {progress:3}% | {eta:19}</code>",
+            "/torrent{num}<code> | {name} | {size:6} Mb | {progress:3}% | {eta:19}</code>",
             num = self.num,
-            name = self.name,
+            // pad before escaping, so entities don't break alignment
+            name = html::escape(&format!("{:20}", self.name)),
             size = self.size,
             progress = self.progress,
             eta = self.eta
@@ -193,12 +203,11 @@ impl QbCommandAction for QListAction {
         if !self.records.is_empty() {
             self.records
                 .iter()
-                .cloned()
                 .map(|record| record.to_string())
                 .collect::<Vec<_>>()
                 .join("\n")
         } else {
-            "Failed to get Qbittorrent response".to_string()
+            "There are no torrents".to_string()
         }
     }
 }
@@ -1,7 +1,9 @@
 pub mod cmd_list;
 pub mod download;
+pub mod limit;
 pub mod list;
 pub mod pause_resume;
+pub mod registry;
 pub mod simple;
 
 pub trait QbCommandAction {
@@ -3,12 +3,10 @@ use std::time::Duration;
 use anyhow::{anyhow, Result};
 use fure::backoff::fixed;
 use fure::policies::{attempts, backoff};
-use serde_json::Value;
 
-use crate::bot::commands::list::QListAction;
 use crate::bot::qb_client::QbClient;
 
-use super::{cmd_list::QPause, QbCommandAction};
+use super::QbCommandAction;
 
 pub struct QPauseResumeAction {
     status: Result<()>,
@@ -30,78 +28,48 @@ impl QPauseResumeAction {
         }
     }
 
-    fn check_paused(&self, state: &str) -> Result<()> {
-        if state.contains("paused") {
+    /// `paused` is the torrent state after the action
+    fn check(&self, paused: bool) -> Result<()> {
+        if paused == (self.action == "pause") {
             Ok(())
         } else {
             Err(anyhow!(format!("Failed to {}", self.action)))
         }
     }
 
-    fn check_resumed(&self, state: &str) -> Result<()> {
-        if !state.contains("paused") {
-            Ok(())
-        } else {
-            Err(anyhow!(format!("Failed to {}", self.action)))
-        }
-    }
-
-    fn check(&self, state: &str) -> Result<()> {
-        if self.action == "pause" {
-            self.check_paused(state)
-        } else {
-            self.check_resumed(state)
-        }
-    }
-
     async fn check_state(&self, client: &QbClient, hash: &str) -> Result<()> {
-        let parse_and_check_state = |items: Value| {
-            let item = items
-                .as_array()?
-                .iter()
-                .find(|item| item.get("hash").unwrap() == hash)?;
-            let state = item.as_object()?.get("state")?.to_owned().to_string();
-            Some(state)
-        };
         let get_and_check_state = || async {
-            let items = QListAction::get(client).await?;
-            let state =
-                parse_and_check_state(items).ok_or_else(|| anyhow!("Failed to parse state"))?;
-            self.check(&state)
+            let state = client
+                .sync()
+                .await?
+                .torrent(hash)
+                .and_then(|torrent| torrent.get("state")?.as_str().map(String::from))
+                .ok_or_else(|| anyhow!("Failed to parse state"))?;
+            // state names depend on torrent client version
+            self.check(client.backend().is_paused(&state))
         };
         let policy = attempts(backoff(fixed(Duration::from_millis(500))), 3);
         fure::retry(get_and_check_state, policy).await
     }
 
-    pub async fn act(mut self, client: &mut QbClient, id: usize) -> Self {
-        let hash_opt = if let Ok(cached_list) = client.get_cached_list().await {
-            || -> Option<String> {
-                let hash = cached_list.get_record_by_num(id)?.get_hash();
-                Some(hash)
-            }()
+    /// Pause or resume the torrent and wait until its state changes
+    pub async fn act_hash(mut self, client: &QbClient, hash: &str) -> Self {
+        let backend = client.backend();
+        let send_res = if self.action == "pause" {
+            backend.pause(hash).await
         } else {
-            None
+            backend.resume(hash).await
+        };
+        self.status = match send_res {
+            Ok(()) => self.check_state(client, hash).await,
+            Err(err) => Err(err.context("Failed to send request to torrent client")),
         };
-
-        if hash_opt.is_none() {
-            self.status = Err(anyhow!("ID to hash conversion failed"));
-            return self;
-        }
-        let hash = hash_opt.unwrap();
-        let qpost_res = client
-            .qpost(
-                format!("/torrents/{}", self.action).as_str(),
-                QPause {
-                    hashes: hash.to_string(),
-                },
-            )
-            .await;
-        if qpost_res.is_err() {
-            self.status = Err(anyhow!("Failed to send request to Qbittorrent"))
-        }
-        self.status = self.check_state(client, &hash).await;
         self
     }
+
+    pub fn is_ok(&self) -> bool {
+        self.status.is_ok()
+    }
 }
 
 impl QbCommandAction for QPauseResumeAction {
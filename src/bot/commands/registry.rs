@@ -0,0 +1,469 @@
+//! Commands known by the bot with their arguments, aliases and help.
+//!
+//! Every command is parsed from any menu, e.g. `/pause 3`, `/limit 3 dl=2M` or
+//! `/list seeding sort=ratio`, ids may also be glued to the name like in `/torrent3`.
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use crate::bot::config::Role;
+use crate::bot::messages::BotCommand;
+
+use super::limit::parse_speed;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandKind {
+    Main,
+    Help,
+    List,
+    Mine,
+    Download,
+    Instance,
+    Torrent,
+    Pause,
+    Resume,
+    Limit,
+    Back,
+    Reload,
+    Audit,
+}
+
+#[derive(Debug)]
+pub enum ArgKind {
+    /// Number of torrent or instance
+    Id,
+    /// One of the listed words
+    Choice(&'static [&'static str]),
+    /// Speed in bytes per second with optional K, M or G suffix
+    Speed,
+    /// Number of items to show
+    Count,
+}
+
+#[derive(Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+    /// Passed as `name=value` instead of position
+    pub keyed: bool,
+}
+
+/// Menu that has to be open to omit the id argument
+#[derive(Debug, PartialEq, Eq)]
+pub enum MenuContext {
+    Any,
+    TorrentPage,
+}
+
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub kind: CommandKind,
+    pub args: &'static [ArgSpec],
+    pub help: &'static str,
+    pub context: MenuContext,
+    /// Changes torrents, so it is hidden from group chats suggestions
+    pub mutating: bool,
+    /// Only admins can run it
+    pub admin: bool,
+}
+
+/// `all` lists torrents of all instances, other words filter torrents by state
+pub const LIST_FILTERS: &[&str] = &["all", "downloading", "seeding", "completed", "paused"];
+pub const LIST_SORTS: &[&str] = &["name", "size", "progress", "ratio"];
+
+const ID: ArgSpec = ArgSpec {
+    name: "id",
+    kind: ArgKind::Id,
+    required: true,
+    keyed: false,
+};
+
+const CONTEXT_ID: ArgSpec = ArgSpec {
+    required: false,
+    ..ID
+};
+
+pub static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "main",
+        aliases: &["start"],
+        kind: CommandKind::Main,
+        args: &[],
+        help: "Go to main menu",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "help",
+        aliases: &[],
+        kind: CommandKind::Help,
+        args: &[],
+        help: "Show help for all commands",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "list",
+        aliases: &["ls"],
+        kind: CommandKind::List,
+        args: &[
+            ArgSpec {
+                name: "filter",
+                kind: ArgKind::Choice(LIST_FILTERS),
+                required: false,
+                keyed: false,
+            },
+            ArgSpec {
+                name: "sort",
+                kind: ArgKind::Choice(LIST_SORTS),
+                required: false,
+                keyed: true,
+            },
+        ],
+        help: "List torrents, \"all\" lists torrents of all instances",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "mine",
+        aliases: &[],
+        kind: CommandKind::Mine,
+        args: &[],
+        help: "List torrents you added",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "download",
+        aliases: &["add"],
+        kind: CommandKind::Download,
+        args: &[],
+        help: "Start downloading by link or attached file",
+        context: MenuContext::Any,
+        mutating: true,
+        admin: false,
+    },
+    CommandSpec {
+        name: "instance",
+        aliases: &[],
+        kind: CommandKind::Instance,
+        args: &[ArgSpec {
+            required: false,
+            ..ID
+        }],
+        help: "Show and switch torrent client instances",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "torrent",
+        aliases: &[],
+        kind: CommandKind::Torrent,
+        args: &[ID],
+        help: "Show torrent page",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "pause",
+        aliases: &["stop"],
+        kind: CommandKind::Pause,
+        args: &[CONTEXT_ID],
+        help: "Pause torrent",
+        context: MenuContext::TorrentPage,
+        mutating: true,
+        admin: false,
+    },
+    CommandSpec {
+        name: "resume",
+        aliases: &["unpause"],
+        kind: CommandKind::Resume,
+        args: &[CONTEXT_ID],
+        help: "Resume torrent",
+        context: MenuContext::TorrentPage,
+        mutating: true,
+        admin: false,
+    },
+    CommandSpec {
+        name: "limit",
+        aliases: &[],
+        kind: CommandKind::Limit,
+        args: &[
+            CONTEXT_ID,
+            ArgSpec {
+                name: "dl",
+                kind: ArgKind::Speed,
+                required: false,
+                keyed: true,
+            },
+            ArgSpec {
+                name: "up",
+                kind: ArgKind::Speed,
+                required: false,
+                keyed: true,
+            },
+        ],
+        help: "Limit torrent speed, e.g. dl=2M up=512K, 0 removes the limit",
+        context: MenuContext::TorrentPage,
+        mutating: true,
+        admin: false,
+    },
+    CommandSpec {
+        name: "back",
+        aliases: &[],
+        kind: CommandKind::Back,
+        args: &[],
+        help: "Go to previous menu",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: false,
+    },
+    CommandSpec {
+        name: "reload",
+        aliases: &[],
+        kind: CommandKind::Reload,
+        args: &[],
+        help: "Reload users, notification and display settings from the config",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: true,
+    },
+    CommandSpec {
+        name: "audit",
+        aliases: &[],
+        kind: CommandKind::Audit,
+        args: &[ArgSpec {
+            name: "n",
+            kind: ArgKind::Count,
+            required: false,
+            keyed: false,
+        }],
+        help: "Show recent actions that changed torrents",
+        context: MenuContext::Any,
+        mutating: false,
+        admin: true,
+    },
+];
+
+impl ArgKind {
+    fn placeholder(&self) -> String {
+        match self {
+            Self::Id => String::from("<id>"),
+            Self::Choice(choices) => choices.join("|"),
+            Self::Speed => String::from("<speed>"),
+            Self::Count => String::from("<n>"),
+        }
+    }
+
+    fn check(&self, value: &str) -> Result<(), String> {
+        let valid = match self {
+            Self::Id | Self::Count => value.parse::<usize>().is_ok(),
+            Self::Choice(choices) => choices.contains(&value),
+            Self::Speed => parse_speed(value).is_some(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!(
+                "Wrong value \"{}\", expected {}",
+                value,
+                self.placeholder()
+            ))
+        }
+    }
+}
+
+impl ArgSpec {
+    fn usage(&self) -> String {
+        let placeholder = self.kind.placeholder();
+        match (self.keyed, self.required) {
+            (true, true) => format!("{}={}", self.name, placeholder),
+            (true, false) => format!("[{}={}]", self.name, placeholder),
+            (false, true) => placeholder,
+            (false, false) => format!(
+                "[{}]",
+                placeholder.trim_start_matches('<').trim_end_matches('>')
+            ),
+        }
+    }
+}
+
+impl CommandSpec {
+    pub fn command(&self) -> String {
+        format!("/{}", self.name)
+    }
+
+    /// Example: `/limit [id] [dl=<speed>] [up=<speed>]`
+    pub fn usage(&self) -> String {
+        std::iter::once(self.command())
+            .chain(self.args.iter().map(ArgSpec::usage))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn help_line(&self) -> String {
+        let aliases = self
+            .aliases
+            .iter()
+            .map(|alias| format!("/{}", alias))
+            .collect::<Vec<_>>();
+        if aliases.is_empty() {
+            format!("{} - {}", self.usage(), self.help)
+        } else {
+            format!(
+                "{} - {} (also {})",
+                self.usage(),
+                self.help,
+                aliases.join(", ")
+            )
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+}
+
+/// Commands `role` can run for Telegram suggestions
+pub fn bot_commands(role: Role) -> Vec<BotCommand> {
+    let mut commands = COMMANDS
+        .iter()
+        .filter(|spec| role.allows(spec))
+        .map(|spec| BotCommand {
+            command: spec.name.to_string(),
+            description: spec.help.to_string(),
+        })
+        .collect::<Vec<_>>();
+    commands.sort_by(|a, b| a.command.cmp(&b.command));
+    commands
+}
+
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.matches(name))
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// Text doesn't start with `/`
+    NotCommand,
+    Unknown(String),
+    Usage {
+        spec: &'static CommandSpec,
+        reason: String,
+    },
+}
+
+impl ParseError {
+    pub fn usage(spec: &'static CommandSpec, reason: impl Into<String>) -> Self {
+        Self::Usage {
+            spec,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotCommand => write!(f, "Commands start with /"),
+            Self::Unknown(name) => write!(f, "Unknown command /{}, see /help", name),
+            Self::Usage { spec, reason } => write!(f, "{}\nUsage: {}", reason, spec.usage()),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub struct ParsedCommand {
+    pub spec: &'static CommandSpec,
+    values: HashMap<&'static str, String>,
+}
+
+impl ParsedCommand {
+    pub fn kind(&self) -> CommandKind {
+        self.spec.kind
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Values are checked while parsing, so conversions can't fail here
+    pub fn id(&self) -> Option<usize> {
+        self.get("id")?.parse().ok()
+    }
+
+    pub fn count(&self) -> Option<usize> {
+        self.get("n")?.parse().ok()
+    }
+
+    pub fn speed(&self, name: &str) -> Option<u64> {
+        parse_speed(self.get(name)?)
+    }
+}
+
+/// Split `torrent3` into `torrent` and `3`
+fn split_glued_id(word: &str) -> (&str, Option<&str>) {
+    match word.find(|c: char| c.is_ascii_digit()) {
+        Some(pos) => (&word[..pos], Some(&word[pos..])),
+        None => (word, None),
+    }
+}
+
+pub fn parse(text: &str) -> Result<ParsedCommand, ParseError> {
+    let mut words = text.split_whitespace();
+    let first = words
+        .next()
+        .and_then(|word| word.strip_prefix('/'))
+        .ok_or(ParseError::NotCommand)?
+        .to_lowercase();
+    // group chats add bot name: /list@qbitbot
+    let first = first.split('@').next().unwrap_or_default();
+    let (name, glued) = split_glued_id(first);
+    let spec = find(name).ok_or_else(|| ParseError::Unknown(first.to_string()))?;
+
+    let mut values = HashMap::new();
+    let mut positional = spec.args.iter().filter(|arg| !arg.keyed);
+    for word in glued.into_iter().chain(words) {
+        let (arg, value) = match word.split_once('=') {
+            Some((key, value)) => {
+                let arg = spec
+                    .args
+                    .iter()
+                    .find(|arg| arg.keyed && arg.name == key)
+                    .ok_or_else(|| ParseError::usage(spec, format!("Unknown option {}", key)))?;
+                (arg, value)
+            }
+            None => {
+                let arg = positional.next().ok_or_else(|| {
+                    ParseError::usage(spec, format!("Unexpected argument {}", word))
+                })?;
+                (arg, word)
+            }
+        };
+        arg.kind
+            .check(value)
+            .map_err(|reason| ParseError::usage(spec, reason))?;
+        values.insert(arg.name, value.to_string());
+    }
+    if let Some(missing) = spec
+        .args
+        .iter()
+        .find(|arg| arg.required && !values.contains_key(arg.name))
+    {
+        return Err(ParseError::usage(
+            spec,
+            format!("Missing argument {}", missing.name),
+        ));
+    }
+    Ok(ParsedCommand { spec, values })
+}
@@ -1,16 +1,24 @@
-use crate::bot::qb_chat::MenuValue;
-use itertools::Itertools;
+use crate::bot::config::Role;
+use crate::bot::html;
 
+use super::registry::COMMANDS;
 use super::QbCommandAction;
 
-pub struct QHelp {}
+/// Help for the commands `role` can run
+pub struct QHelp {
+    pub role: Role,
+}
 
 impl QbCommandAction for QHelp {
     fn action_result_to_string(&self) -> String {
-        MenuValue::generate_cmds()
+        let mut commands = COMMANDS
             .iter()
-            .sorted()
-            .map(|(cmd, menu_value)| format!("{} - {}", cmd, menu_value.get_help()))
+            .filter(|spec| self.role.allows(spec))
+            .collect::<Vec<_>>();
+        commands.sort_by_key(|spec| spec.name);
+        commands
+            .into_iter()
+            .map(|spec| html::escape(&spec.help_line()))
             .collect::<Vec<_>>()
             .join("\n")
     }
@@ -1,38 +1,912 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
-macro_rules! get_dotenv_var_or_panic {
-    ($var:literal) => {
-        dotenv::var($var).unwrap_or_else(|_| panic!("Please provide {} in the .env file", $var))
-    };
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use crate::bot::commands::registry::{CommandSpec, LIST_SORTS};
+
+/// Config file read by [QbConfig::load], `.env` is used when it doesn't exist
+pub const DEFAULT_PATH: &str = "qbitbot.toml";
+
+/// Shown instead of secrets in Debug output, which gets to logs
+const REDACTED: &str = "<redacted>";
+
+/// Torrent client API spoken by the bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Qbittorrent,
+    Transmission,
 }
 
-#[derive(Debug, Clone)]
-pub struct QbConfig {
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "qbittorrent" => Ok(Self::Qbittorrent),
+            "transmission" => Ok(Self::Transmission),
+            other => Err(anyhow!("Unknown backend {}", other)),
+        }
+    }
+}
+
+/// What a user is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    /// Can't run admin commands like /reload
+    User,
+    /// Can only run commands that don't change torrents
+    Viewer,
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "admin" => Ok(Self::Admin),
+            "user" => Ok(Self::User),
+            "viewer" => Ok(Self::Viewer),
+            other => Err(anyhow!(
+                "Unknown role {}, expected admin|user|viewer",
+                other
+            )),
+        }
+    }
+}
+
+impl Role {
+    pub fn allows(self, spec: &CommandSpec) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::User => !spec.admin,
+            Role::Viewer => !spec.admin && !spec.mutating,
+        }
+    }
+
+    /// Changes without a chat command, e.g. deletion with the HTTP API
+    pub fn can_change(self) -> bool {
+        self != Role::Viewer
+    }
+}
+
+/// Connection settings of one torrent client
+#[derive(Clone, PartialEq, Eq)]
+pub struct InstanceConfig {
+    pub name: String,
+    pub backend: BackendKind,
     pub location: String,
     pub user: String,
     pub password: String,
-    pub admins: HashSet<String>,
+}
+
+impl fmt::Debug for InstanceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstanceConfig")
+            .field("name", &self.name)
+            .field("backend", &self.backend)
+            .field("location", &self.location)
+            .field("user", &self.user)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+/// Receive updates with webhook instead of long polling
+#[derive(Clone, PartialEq, Eq)]
+pub struct WebhookConfig {
+    /// Public address of the webhook, e.g. behind a reverse proxy
+    pub url: String,
+    pub listen: SocketAddr,
+    /// Path of the embedded server that gets updates
+    pub path: String,
+    /// Value of X-Telegram-Bot-Api-Secret-Token header sent by Telegram, without it
+    /// anyone who finds the URL could send updates
+    pub secret: String,
+}
+
+impl fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("url", &self.url)
+            .field("listen", &self.listen)
+            .field("path", &self.path)
+            .field("secret", &REDACTED)
+            .finish()
+    }
+}
+
+/// Token of the HTTP API, requests with it act as `user`
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiToken {
+    /// Shown in logs instead of the token
+    pub name: String,
+    pub token: String,
+    /// Telegram username from `[users]`, its role limits the requests
+    pub user: String,
+    /// Chat notified about torrents the token subscribes to
+    pub chat_id: Option<i64>,
+}
+
+impl fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiToken")
+            .field("name", &self.name)
+            .field("token", &REDACTED)
+            .field("user", &self.user)
+            .field("chat_id", &self.chat_id)
+            .finish()
+    }
+}
+
+/// Embedded HTTP server with the JSON API for dashboards and scripts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiConfig {
+    pub listen: SocketAddr,
+    pub tokens: Vec<ApiToken>,
+}
+
+/// Prometheus metrics server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsConfig {
+    pub listen: SocketAddr,
+    /// Export speed and torrent counts of every instance, it costs a sync per scrape
+    pub torrents: bool,
+}
+
+/// Server of `/healthz` alone, for supervisors of a bot without metrics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthConfig {
+    pub listen: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationConfig {
+    /// Tell the chat that added a torrent when it's done
+    pub completed: bool,
+    /// How often watched torrents are checked for completion
+    pub check_interval: Duration,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            completed: true,
+            check_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisplayConfig {
+    /// Sort of /list when the command doesn't set one, torrent client order by default
+    pub list_sort: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct QbConfig {
+    pub instances: Vec<InstanceConfig>,
+    pub users: HashMap<String, Role>,
+    /// Non-admins change only torrents they added
+    pub own_torrents_only: bool,
     pub log_level: String,
     pub token: String,
+    pub webhook: Option<WebhookConfig>,
+    pub api: Option<ApiConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub health: Option<HealthConfig>,
+    /// Watched torrents are saved there on shutdown
+    pub state_file: String,
+    /// Actions that change torrents are appended there as JSON lines
+    pub audit_file: String,
+    pub notifications: NotificationConfig,
+    pub display: DisplayConfig,
+}
+
+impl fmt::Debug for QbConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QbConfig")
+            .field("instances", &self.instances)
+            .field("users", &self.users)
+            .field("own_torrents_only", &self.own_torrents_only)
+            .field("log_level", &self.log_level)
+            .field("token", &REDACTED)
+            .field("webhook", &self.webhook)
+            .field("api", &self.api)
+            .field("metrics", &self.metrics)
+            .field("health", &self.health)
+            .field("state_file", &self.state_file)
+            .field("audit_file", &self.audit_file)
+            .field("notifications", &self.notifications)
+            .field("display", &self.display)
+            .finish()
+    }
+}
+
+/// All problems found in the config, so they can be fixed at once
+#[derive(Debug)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl ConfigError {
+    fn single(error: String) -> Self {
+        Self {
+            errors: vec![error],
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config:")?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    token: Option<String>,
+    token_file: Option<String>,
+    log_level: Option<String>,
+    state_file: Option<String>,
+    audit_file: Option<String>,
+    #[serde(default)]
+    instances: Vec<FileInstance>,
+    /// Username to role
+    #[serde(default)]
+    users: BTreeMap<String, String>,
+    own_torrents_only: Option<bool>,
+    #[serde(default)]
+    notifications: FileNotifications,
+    #[serde(default)]
+    display: FileDisplay,
+    webhook: Option<FileWebhook>,
+    api: Option<FileApi>,
+    metrics: Option<FileMetrics>,
+    health: Option<FileHealth>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileInstance {
+    name: String,
+    backend: Option<String>,
+    location: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    password_file: Option<String>,
+    /// Prefix of the variables overriding the instance settings, e.g. `SEEDBOX_`
+    #[serde(skip)]
+    env_prefix: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileNotifications {
+    completed: Option<bool>,
+    /// Seconds
+    check_interval: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileDisplay {
+    list_sort: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileWebhook {
+    url: Option<String>,
+    listen: Option<String>,
+    path: Option<String>,
+    secret: Option<String>,
+    secret_file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileApi {
+    listen: Option<String>,
+    /// Name to token settings
+    #[serde(default)]
+    tokens: BTreeMap<String, FileApiToken>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileApiToken {
+    token: Option<String>,
+    token_file: Option<String>,
+    user: String,
+    chat_id: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileMetrics {
+    listen: Option<String>,
+    torrents: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileHealth {
+    listen: Option<String>,
+}
+
+/// Variables of the environment and a `.env` config file, the environment wins.
+/// The file is not exported, so it's read again on every load.
+#[derive(Default)]
+struct Vars {
+    file: HashMap<String, String>,
+}
+
+impl Vars {
+    /// Parse `KEY=value` lines, values may be quoted
+    fn read(path: &str) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| ConfigError::single(format!("Failed to read {}: {}", path, err)))?;
+        let file = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                let line = line.strip_prefix("export ").unwrap_or(line);
+                if line.starts_with('#') {
+                    return None;
+                }
+                let (key, value) = line.split_once('=')?;
+                let value = value.trim();
+                let unquoted = ['\'', '"']
+                    .iter()
+                    .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+                    .unwrap_or(value);
+                Some((key.trim().to_string(), unquoted.to_string()))
+            })
+            .collect();
+        Ok(Self { file })
+    }
+
+    fn get(&self, var: &str) -> Option<String> {
+        env::var(var).ok().or_else(|| self.file.get(var).cloned())
+    }
+
+    /// Replace `value` with the variable if it's set
+    fn override_with(&self, value: &mut Option<String>, var: &str) {
+        if let Some(var_value) = self.get(var) {
+            *value = Some(var_value);
+        }
+    }
+
+    /// Like [override_with](Self::override_with) for secrets, which can also be read from
+    /// a file named by `{var}_FILE`, e.g. a systemd credential or a Docker secret
+    fn override_secret(&self, value: &mut Option<String>, file: &mut Option<String>, var: &str) {
+        if let Some(var_value) = self.get(var) {
+            *value = Some(var_value);
+            *file = None;
+        } else if let Some(path) = self.get(&format!("{}_FILE", var)) {
+            *value = None;
+            *file = Some(path);
+        }
+    }
+}
+
+/// Secret from `file` if it's set, without the trailing newline
+fn read_secret(
+    value: Option<String>,
+    file: Option<String>,
+    key: &str,
+) -> Result<Option<String>, String> {
+    match (value, file) {
+        (value, None) => Ok(value),
+        (Some(_), Some(_)) => Err(format!("{} is set both directly and with a file", key)),
+        (None, Some(path)) => match fs::read_to_string(&path) {
+            Ok(secret) => Ok(Some(secret.trim_end_matches(&['\r', '\n'][..]).to_string())),
+            Err(err) => Err(format!("Failed to read {} from {}: {}", key, path, err)),
+        },
+    }
+}
+
+fn require_secret(
+    value: Option<String>,
+    file: Option<String>,
+    key: &str,
+    var: &str,
+    errors: &mut Vec<String>,
+) -> String {
+    match read_secret(value, file, key) {
+        Ok(value) => require(value, key, &format!("{} or {}_FILE", var, var), errors),
+        Err(err) => {
+            errors.push(err);
+            String::new()
+        }
+    }
+}
+
+fn require(value: Option<String>, key: &str, var: &str, errors: &mut Vec<String>) -> String {
+    match value {
+        Some(value) if !value.trim().is_empty() => value,
+        _ => {
+            errors.push(format!(
+                "{} is not set, add it to the config or set {}",
+                key, var
+            ));
+            String::new()
+        }
+    }
+}
+
+impl FileConfig {
+    /// Variables win over the file, so a deployment can change a setting without editing it
+    fn apply_env(&mut self, vars: &Vars) {
+        vars.override_secret(&mut self.token, &mut self.token_file, "TOKEN");
+        vars.override_with(&mut self.log_level, "LOG_LEVEL");
+        vars.override_with(&mut self.state_file, "STATE_FILE");
+        vars.override_with(&mut self.audit_file, "AUDIT_FILE");
+        if let Some(admins) = vars.get("ADMIN") {
+            for name in admins.split_whitespace() {
+                self.users.insert(name.to_string(), String::from("admin"));
+            }
+        }
+
+        // instances are listed in INSTANCES by name, without them there is one named "default"
+        if let Some(names) = vars.get("INSTANCES") {
+            for name in names.split_whitespace() {
+                if !self.instances.iter().any(|instance| instance.name == name) {
+                    self.instances.push(FileInstance {
+                        name: name.to_string(),
+                        ..FileInstance::default()
+                    });
+                }
+            }
+        }
+        if self.instances.is_empty() {
+            self.instances.push(FileInstance {
+                name: String::from("default"),
+                ..FileInstance::default()
+            });
+        } else {
+            for instance in &mut self.instances {
+                instance.env_prefix = format!("{}_", instance.name.to_uppercase());
+            }
+        }
+        for instance in &mut self.instances {
+            let prefix = instance.env_prefix.clone();
+            vars.override_with(&mut instance.backend, &format!("{}BACKEND", prefix));
+            vars.override_with(&mut instance.location, &format!("{}QBLOCATION", prefix));
+            vars.override_with(&mut instance.user, &format!("{}QBUSER", prefix));
+            vars.override_secret(
+                &mut instance.password,
+                &mut instance.password_file,
+                &format!("{}QBPASS", prefix),
+            );
+        }
+
+        if vars.get("WEBHOOK_URL").is_some() && self.webhook.is_none() {
+            self.webhook = Some(FileWebhook::default());
+        }
+        if let Some(webhook) = &mut self.webhook {
+            vars.override_with(&mut webhook.url, "WEBHOOK_URL");
+            vars.override_with(&mut webhook.listen, "WEBHOOK_LISTEN");
+            vars.override_with(&mut webhook.path, "WEBHOOK_PATH");
+            vars.override_secret(
+                &mut webhook.secret,
+                &mut webhook.secret_file,
+                "WEBHOOK_SECRET",
+            );
+        }
+        if let Some(api) = &mut self.api {
+            vars.override_with(&mut api.listen, "API_LISTEN");
+        }
+        if vars.get("METRICS_LISTEN").is_some() && self.metrics.is_none() {
+            self.metrics = Some(FileMetrics::default());
+        }
+        if let Some(metrics) = &mut self.metrics {
+            vars.override_with(&mut metrics.listen, "METRICS_LISTEN");
+        }
+        if vars.get("HEALTH_LISTEN").is_some() && self.health.is_none() {
+            self.health = Some(FileHealth::default());
+        }
+        if let Some(health) = &mut self.health {
+            vars.override_with(&mut health.listen, "HEALTH_LISTEN");
+        }
+    }
+
+    /// The token is optional for frontends that don't talk to Telegram
+    fn validate(self, token_required: bool) -> Result<QbConfig, ConfigError> {
+        let mut errors = Vec::new();
+        let token = if token_required {
+            require_secret(self.token, self.token_file, "token", "TOKEN", &mut errors)
+        } else {
+            read_secret(self.token, self.token_file, "token")
+                .unwrap_or_else(|err| {
+                    errors.push(err);
+                    None
+                })
+                .unwrap_or_default()
+        };
+
+        let mut instances: Vec<InstanceConfig> = Vec::new();
+        for instance in self.instances {
+            let name = instance.name;
+            let prefix = instance.env_prefix;
+            let key = |field: &str| format!("instance {} {}", name, field);
+            if instances.iter().any(|other| other.name == name) {
+                errors.push(format!("Instance {} is listed twice", name));
+            }
+            let backend = match instance.backend.map(|backend| backend.parse()) {
+                None => BackendKind::Qbittorrent,
+                Some(Ok(backend)) => backend,
+                Some(Err(err)) => {
+                    errors.push(format!("{}: {}", key("backend"), err));
+                    BackendKind::Qbittorrent
+                }
+            };
+            let location = require(
+                instance.location,
+                &key("location"),
+                &format!("{}QBLOCATION", prefix),
+                &mut errors,
+            );
+            if !location.is_empty()
+                && !location.starts_with("http://")
+                && !location.starts_with("https://")
+            {
+                errors.push(format!(
+                    "{} should start with http:// or https://, got {}",
+                    key("location"),
+                    location
+                ));
+            }
+            let user = require(
+                instance.user,
+                &key("user"),
+                &format!("{}QBUSER", prefix),
+                &mut errors,
+            );
+            let password = require_secret(
+                instance.password,
+                instance.password_file,
+                &key("password"),
+                &format!("{}QBPASS", prefix),
+                &mut errors,
+            );
+            instances.push(InstanceConfig {
+                name,
+                backend,
+                location,
+                user,
+                password,
+            });
+        }
+
+        let mut users = HashMap::new();
+        for (name, role) in self.users {
+            let name = name.trim_start_matches('@').to_string();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                errors.push(format!("User name {:?} is not a Telegram username", name));
+            }
+            match role.parse() {
+                Ok(role) => {
+                    users.insert(name, role);
+                }
+                Err(err) => errors.push(format!("User {}: {}", name, err)),
+            }
+        }
+        if !users.values().any(|role| *role == Role::Admin) {
+            errors.push(String::from(
+                "There are no admins, add them to [users] or set ADMIN",
+            ));
+        }
+
+        let notifications = NotificationConfig {
+            completed: self.notifications.completed.unwrap_or(true),
+            check_interval: match self.notifications.check_interval {
+                Some(0) => {
+                    errors.push(String::from(
+                        "notifications check_interval should be positive",
+                    ));
+                    Duration::from_secs(1)
+                }
+                Some(secs) => Duration::from_secs(secs),
+                None => Duration::from_secs(1),
+            },
+        };
+
+        let list_sort = self.display.list_sort;
+        if let Some(sort) = &list_sort {
+            if !LIST_SORTS.contains(&sort.as_str()) {
+                errors.push(format!(
+                    "display list_sort should be one of {}, got {}",
+                    LIST_SORTS.join("|"),
+                    sort
+                ));
+            }
+        }
+
+        let webhook = self
+            .webhook
+            .map(|webhook| Self::validate_webhook(webhook, &mut errors));
+        let api = self
+            .api
+            .map(|api| Self::validate_api(api, &users, &mut errors));
+        let metrics = self.metrics.map(|metrics| {
+            let listen = metrics
+                .listen
+                .unwrap_or_else(|| String::from("127.0.0.1:9184"));
+            MetricsConfig {
+                listen: listen.parse().unwrap_or_else(|err| {
+                    errors.push(format!(
+                        "metrics listen {} is not an address: {}",
+                        listen, err
+                    ));
+                    ([127, 0, 0, 1], 9184).into()
+                }),
+                torrents: metrics.torrents.unwrap_or(false),
+            }
+        });
+        let health = self.health.map(|health| {
+            let listen = health
+                .listen
+                .unwrap_or_else(|| String::from("127.0.0.1:9185"));
+            HealthConfig {
+                listen: listen.parse().unwrap_or_else(|err| {
+                    errors.push(format!(
+                        "health listen {} is not an address: {}",
+                        listen, err
+                    ));
+                    ([127, 0, 0, 1], 9185).into()
+                }),
+            }
+        });
+
+        if !errors.is_empty() {
+            return Err(ConfigError { errors });
+        }
+        Ok(QbConfig {
+            instances,
+            users,
+            own_torrents_only: self.own_torrents_only.unwrap_or(false),
+            log_level: self.log_level.unwrap_or_else(|| String::from("info")),
+            token,
+            webhook,
+            api,
+            metrics,
+            health,
+            state_file: self
+                .state_file
+                .unwrap_or_else(|| String::from("state.json")),
+            audit_file: self
+                .audit_file
+                .unwrap_or_else(|| String::from("audit.jsonl")),
+            notifications,
+            display: DisplayConfig { list_sort },
+        })
+    }
+
+    fn validate_webhook(webhook: FileWebhook, errors: &mut Vec<String>) -> WebhookConfig {
+        let url = require(webhook.url, "webhook url", "WEBHOOK_URL", errors);
+        let listen = webhook
+            .listen
+            .unwrap_or_else(|| String::from("127.0.0.1:8080"));
+        let listen: SocketAddr = listen.parse().unwrap_or_else(|err| {
+            errors.push(format!(
+                "webhook listen {} is not an address: {}",
+                listen, err
+            ));
+            ([127, 0, 0, 1], 8080).into()
+        });
+        let secret = require_secret(
+            webhook.secret,
+            webhook.secret_file,
+            "webhook secret",
+            "WEBHOOK_SECRET",
+            errors,
+        );
+        let valid_secret = secret.len() <= 256
+            && secret
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_secret {
+            errors.push(String::from(
+                "webhook secret should be 1-256 chars of A-Z, a-z, 0-9, _ and -",
+            ));
+        }
+        WebhookConfig {
+            url,
+            listen,
+            path: webhook.path.unwrap_or_else(|| String::from("/webhook")),
+            secret,
+        }
+    }
+
+    fn validate_api(
+        api: FileApi,
+        users: &HashMap<String, Role>,
+        errors: &mut Vec<String>,
+    ) -> ApiConfig {
+        let listen = api.listen.unwrap_or_else(|| String::from("127.0.0.1:8081"));
+        let listen: SocketAddr = listen.parse().unwrap_or_else(|err| {
+            errors.push(format!("api listen {} is not an address: {}", listen, err));
+            ([127, 0, 0, 1], 8081).into()
+        });
+        let mut tokens: Vec<ApiToken> = Vec::new();
+        for (name, token) in api.tokens {
+            let key = format!("api token {}", name);
+            let value = read_secret(token.token, token.token_file, &key).unwrap_or_else(|err| {
+                errors.push(err);
+                None
+            });
+            let value = match value {
+                Some(value) if !value.trim().is_empty() => value,
+                _ => {
+                    errors.push(format!("{} is not set, add token or token_file", key));
+                    String::new()
+                }
+            };
+            if tokens.iter().any(|other| other.token == value) && !value.is_empty() {
+                errors.push(format!("{} is the same as another token", key));
+            }
+            let user = token.user.trim_start_matches('@').to_string();
+            if !users.contains_key(&user) {
+                errors.push(format!("{} user {} is not in [users]", key, user));
+            }
+            tokens.push(ApiToken {
+                name,
+                token: value,
+                user,
+                chat_id: token.chat_id,
+            });
+        }
+        ApiConfig { listen, tokens }
+    }
 }
 
 impl QbConfig {
-    pub fn load_path(path: &str) -> Self {
-        dotenv::from_filename(path).unwrap_or_else(|_| panic!("{} config file was not found!", path));
+    /// Read a TOML config, or a `.env` file of the older versions if `path` doesn't end
+    /// with `.toml`. Environment variables override both.
+    pub fn load_path(path: &str) -> Result<Self, ConfigError> {
+        Self::load_path_with(path, true)
+    }
+
+    /// [DEFAULT_PATH] or `.env` in the working dir, without them only environment is used
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_with(true)
+    }
+
+    /// Config of the terminal frontend, it works without the token
+    pub fn load_local(path: Option<&str>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::load_path_with(path, false),
+            None => Self::load_with(false),
+        }
+    }
+
+    fn load_path_with(path: &str, token_required: bool) -> Result<Self, ConfigError> {
+        let (mut file_config, vars) = if path.ends_with(".toml") {
+            let text = fs::read_to_string(path)
+                .map_err(|err| ConfigError::single(format!("Failed to read {}: {}", path, err)))?;
+            let file_config = toml::from_str(&text)
+                .map_err(|err| ConfigError::single(format!("Failed to parse {}: {}", path, err)))?;
+            // a stray `.env` must not override the explicit config
+            (file_config, Vars::default())
+        } else {
+            (FileConfig::default(), Vars::read(path)?)
+        };
+        file_config.apply_env(&vars);
+        file_config.validate(token_required)
+    }
+
+    fn load_with(token_required: bool) -> Result<Self, ConfigError> {
+        if Path::new(DEFAULT_PATH).exists() {
+            Self::load_path_with(DEFAULT_PATH, token_required)
+        } else if Path::new(".env").exists() {
+            Self::load_path_with(".env", token_required)
+        } else {
+            let mut file_config = FileConfig::default();
+            file_config.apply_env(&Vars::default());
+            file_config.validate(token_required)
+        }
+    }
+
+    pub fn role(&self, username: &str) -> Option<Role> {
+        self.users.get(username).copied()
+    }
+
+    /// Users of `role` can change only torrents they added
+    pub fn restricts_to_own(&self, role: Role) -> bool {
+        self.own_torrents_only && role != Role::Admin
+    }
+
+    /// Running config with settings of `new` that can be changed without restart
+    pub fn reloaded(&self, new: QbConfig) -> QbConfig {
+        // the server keeps listening where it started, only its tokens change
+        let api = match (&self.api, new.api) {
+            (Some(api), Some(new_api)) => Some(ApiConfig {
+                listen: api.listen,
+                tokens: new_api.tokens,
+            }),
+            _ => self.api.clone(),
+        };
         QbConfig {
-            location: get_dotenv_var_or_panic!("QBLOCATION"),
-            user: get_dotenv_var_or_panic!("QBUSER"),
-            password: get_dotenv_var_or_panic!("QBPASS"),
-            admins: get_dotenv_var_or_panic!("ADMIN")
-                .split(' ')
-                .map(String::from)
-                .collect(),
-            token: get_dotenv_var_or_panic!("TOKEN"),
-            log_level: dotenv::var("LOG_LEVEL").unwrap_or_else(|_| String::from("info")),
+            users: new.users,
+            own_torrents_only: new.own_torrents_only,
+            notifications: new.notifications,
+            display: new.display,
+            api,
+            ..self.clone()
         }
     }
 
-    pub fn load() -> Self {
-        Self::load_path(".env")
+    /// Human readable differences with `new`, settings that need restart are mentioned too
+    pub fn changes(&self, new: &QbConfig) -> Vec<String> {
+        let mut changes = Vec::new();
+        let mut names: Vec<&String> = self.users.keys().chain(new.users.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            match (self.users.get(name), new.users.get(name)) {
+                (None, Some(role)) => changes.push(format!("User {} is added as {:?}", name, role)),
+                (Some(_), None) => changes.push(format!("User {} is removed", name)),
+                (Some(old), Some(role)) if old != role => {
+                    changes.push(format!("User {} is {:?} instead of {:?}", name, role, old))
+                }
+                _ => (),
+            }
+        }
+        if self.own_torrents_only != new.own_torrents_only {
+            changes.push(format!("Own torrents only: {}", new.own_torrents_only));
+        }
+        if self.notifications != new.notifications {
+            changes.push(format!("Notifications: {:?}", new.notifications));
+        }
+        if self.display != new.display {
+            changes.push(format!("Display: {:?}", new.display));
+        }
+        if let (Some(api), Some(new_api)) = (&self.api, &new.api) {
+            if api.tokens != new_api.tokens {
+                let names: Vec<&str> = new_api
+                    .tokens
+                    .iter()
+                    .map(|token| token.name.as_str())
+                    .collect();
+                changes.push(format!("API tokens: {}", names.join(", ")));
+            }
+        }
+        let restart = [
+            ("instances", self.instances != new.instances),
+            ("token", self.token != new.token),
+            ("log_level", self.log_level != new.log_level),
+            ("webhook", self.webhook != new.webhook),
+            (
+                "api",
+                self.api.as_ref().map(|api| api.listen) != new.api.as_ref().map(|api| api.listen),
+            ),
+            ("metrics", self.metrics != new.metrics),
+            ("health", self.health != new.health),
+            ("state_file", self.state_file != new.state_file),
+            ("audit_file", self.audit_file != new.audit_file),
+        ];
+        for (key, changed) in restart.iter() {
+            if *changed {
+                changes.push(format!("Changed {} is applied after restart", key));
+            }
+        }
+        changes
     }
 }
@@ -0,0 +1,187 @@
+//! Liveness of the bot for supervisors, served as `/healthz` next to `/metrics` or alone.
+//!
+//! Like metrics, the state is global: the updates loop and torrent clients record it
+//! without a handle to the bot.
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+
+use crate::bot::backend::QbError;
+use crate::bot::config::HealthConfig;
+use crate::bot::http::{Handler, HttpServer};
+use crate::bot::qbot::QbitBot;
+
+/// Delay after the first failed poll, doubled on every next one
+const BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A broken connection fails every poll, so polling is fine after so long without errors
+const RECOVERY: Duration = Duration::from_secs(90);
+
+#[derive(Default)]
+struct InstanceHealth {
+    logged_in: bool,
+    last_success: Option<Instant>,
+    last_error: Option<String>,
+}
+
+struct Health {
+    last_update: Option<Instant>,
+    last_error: Option<Instant>,
+    /// Failed polls in a row
+    errors: u32,
+    /// Instance name to its state
+    instances: BTreeMap<String, InstanceHealth>,
+}
+
+impl Health {
+    /// Polling counts as failing until no error happened for [RECOVERY] after the backoff
+    fn polling_ok(&self) -> bool {
+        match self.last_error {
+            None => true,
+            Some(_) if self.last_update > self.last_error => true,
+            Some(error) => error.elapsed() > backoff(self.errors) + RECOVERY,
+        }
+    }
+}
+
+static HEALTH: Mutex<Health> = Mutex::new(Health {
+    last_update: None,
+    last_error: None,
+    errors: 0,
+    instances: BTreeMap::new(),
+});
+
+/// Delay before polling again after `errors` failures in a row
+pub fn backoff(errors: u32) -> Duration {
+    if errors == 0 {
+        return Duration::from_secs(0);
+    }
+    let factor = 2u32.saturating_pow(errors - 1);
+    min(BACKOFF_INITIAL.saturating_mul(factor), BACKOFF_MAX)
+}
+
+/// Telegram delivered an update, by polling or webhook
+pub fn update_received() {
+    let mut health = HEALTH.lock().unwrap();
+    health.last_update = Some(Instant::now());
+    health.errors = 0;
+}
+
+/// Polling for updates failed, returns the delay before the next poll
+pub fn polling_failed() -> Duration {
+    let mut health = HEALTH.lock().unwrap();
+    if health.polling_ok() {
+        health.errors = 0;
+    }
+    health.last_error = Some(Instant::now());
+    health.errors += 1;
+    backoff(health.errors)
+}
+
+/// Outcome of a torrent client request, rejected credentials mark the instance logged out
+pub fn record_request<T>(instance: &str, res: &Result<T, QbError>) {
+    let mut health = HEALTH.lock().unwrap();
+    let state = health.instances.entry(instance.to_string()).or_default();
+    match res {
+        Ok(_) => {
+            state.logged_in = true;
+            state.last_success = Some(Instant::now());
+            state.last_error = None;
+        }
+        Err(err) => {
+            if let QbError::Auth(_) = err {
+                state.logged_in = false;
+            }
+            state.last_error = Some(err.to_string());
+        }
+    }
+}
+
+fn secs_ago(instant: Option<Instant>) -> Value {
+    instant.map_or(Value::Null, |instant| json!(instant.elapsed().as_secs()))
+}
+
+/// Report of the bot, `false` if it doesn't get updates from Telegram anymore
+pub fn report(qbot: &QbitBot) -> (bool, Value) {
+    let health = HEALTH.lock().unwrap();
+    let polling_ok = health.polling_ok();
+    let mut instances_ok = true;
+    let mut instances = Vec::new();
+    for client in qbot.instances() {
+        let state = health.instances.get(client.name());
+        let logged_in = !client.is_reconnecting() && state.is_some_and(|s| s.logged_in);
+        instances_ok &= logged_in;
+        instances.push(json!({
+            "name": client.name(),
+            "logged_in": logged_in,
+            "last_success_secs": secs_ago(state.and_then(|s| s.last_success)),
+            "last_error": state.and_then(|s| s.last_error.clone()),
+        }));
+    }
+    let status = match (polling_ok, instances_ok) {
+        (false, _) => "failing",
+        (true, false) => "degraded",
+        (true, true) => "ok",
+    };
+    let mode = if qbot.config().webhook.is_some() {
+        "webhook"
+    } else {
+        "polling"
+    };
+    let report = json!({
+        "status": status,
+        "telegram": {
+            "mode": mode,
+            "ok": polling_ok,
+            "last_update_secs": secs_ago(health.last_update),
+            "last_error_secs": secs_ago(health.last_error),
+            "consecutive_errors": health.errors,
+        },
+        "instances": instances,
+    });
+    (polling_ok, report)
+}
+
+/// [report] as a response, 503 if the bot is failing
+pub fn respond(qbot: &QbitBot) -> Response<Body> {
+    let (ok, report) = report(qbot);
+    let status = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(report.to_string()))
+        .unwrap()
+}
+
+struct HealthHandler {
+    qbot: Arc<QbitBot>,
+}
+
+#[async_trait]
+impl Handler for HealthHandler {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        if req.method() == Method::GET && req.uri().path() == "/healthz" {
+            respond(&self.qbot)
+        } else {
+            let mut resp = Response::new(Body::from("Not Found"));
+            *resp.status_mut() = StatusCode::NOT_FOUND;
+            resp
+        }
+    }
+}
+
+/// Server of `/healthz` without metrics
+pub fn bind(config: &HealthConfig, qbot: Arc<QbitBot>) -> Result<HttpServer> {
+    HttpServer::bind(&config.listen, HealthHandler { qbot })
+}
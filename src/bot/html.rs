@@ -0,0 +1,222 @@
+//! Telegram HTML helpers: escaping of untrusted text, splitting of long messages and
+//! rendering for the terminal.
+
+/// Telegram rejects longer messages
+pub const MESSAGE_LIMIT: usize = 4096;
+
+/// Escape text for Telegram HTML parse mode, e.g. torrent names
+///
+/// Example:
+/// ```
+/// # use qbitbot::bot::html::escape;
+/// assert_eq!(escape("Tom & Jerry <1080p>"), "Tom &amp; Jerry &lt;1080p&gt;");
+/// ```
+pub fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+fn len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Length of the tag or entity at the start of `text`, one char otherwise
+fn atom_len(text: &str, html: bool) -> usize {
+    let end = match text.chars().next() {
+        Some('<') if html => text.find('>').map(|pos| pos + 1),
+        Some('&') if html => text.find(';').filter(|&pos| pos <= 10).map(|pos| pos + 1),
+        _ => None,
+    };
+    end.unwrap_or_else(|| text.chars().next().map_or(0, char::len_utf8))
+}
+
+/// Track opening tags of `piece` in `open`, e.g. `<code>` for multiline code
+fn update_tags(open: &mut Vec<String>, piece: &str) {
+    let mut rest = piece;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end + 1,
+            None => break,
+        };
+        let tag = &rest[start..end];
+        if let Some(name) = tag.strip_prefix("</") {
+            let name = name.trim_end_matches('>');
+            if let Some(pos) = open.iter().rposition(|opened| tag_name(opened) == name) {
+                open.remove(pos);
+            }
+        } else {
+            open.push(tag.to_string());
+        }
+        rest = &rest[end..];
+    }
+}
+
+fn tag_name(tag: &str) -> &str {
+    tag.trim_start_matches('<')
+        .trim_end_matches('>')
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+}
+
+fn closing_tags(open: &[String]) -> String {
+    open.iter()
+        .rev()
+        .map(|tag| format!("</{}>", tag_name(tag)))
+        .collect()
+}
+
+/// Split message into parts of at most `limit` chars at line boundaries.
+/// Tags open at the end of a part are closed there and reopened in the next one.
+///
+/// Example:
+/// ```
+/// # use qbitbot::bot::html::split;
+/// let parts = split("<b>first\nsecond</b>", 16, true);
+/// assert_eq!(parts, vec!["<b>first\n</b>", "<b>second</b>"]);
+/// ```
+pub fn split(text: &str, limit: usize, html: bool) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut open = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let mut rest = line;
+        while !rest.is_empty() {
+            let (piece, after) = fit(rest, &open, limit.saturating_sub(len(&part)), html);
+            let fits = piece.len() == rest.len()
+                && len(&part) + len(piece) + len(&closing_tags(&after)) <= limit;
+            // a line is cut only if it doesn't fit into a part of its own
+            if !fits && part.len() > open.concat().len() {
+                part.push_str(&closing_tags(&open));
+                parts.push(std::mem::replace(&mut part, open.concat()));
+                continue;
+            }
+            part.push_str(piece);
+            rest = &rest[piece.len()..];
+            open = after;
+        }
+    }
+    if !part.is_empty() {
+        part.push_str(&closing_tags(&open));
+        parts.push(part);
+    }
+    parts
+}
+
+/// Longest start of `line` that fits into `room` chars with the tags closed after it, and
+/// the tags open then. Tags and entities are never cut, the first one is taken anyway.
+fn fit<'a>(line: &'a str, open: &[String], room: usize, html: bool) -> (&'a str, Vec<String>) {
+    let mut tags = open.to_vec();
+    let mut end = 0;
+    let mut count = 0;
+    while end < line.len() {
+        let atom = &line[end..end + atom_len(&line[end..], html)];
+        let mut after = tags.clone();
+        if html {
+            update_tags(&mut after, atom);
+        }
+        if count + len(atom) + len(&closing_tags(&after)) > room && end > 0 {
+            break;
+        }
+        count += len(atom);
+        end += atom.len();
+        tags = after;
+    }
+    (&line[..end], tags)
+}
+
+fn unescape(entity: &str) -> &str {
+    match entity {
+        "&amp;" => "&",
+        "&lt;" => "<",
+        "&gt;" => ">",
+        "&quot;" => "\"",
+        other => other,
+    }
+}
+
+/// Plain text of a message, for output without markup like the command line
+///
+/// Example:
+/// ```
+/// # use qbitbot::bot::html::strip;
+/// assert_eq!(strip("<b>Tom &amp; Jerry</b>"), "Tom & Jerry");
+/// ```
+pub fn strip(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let atom = atom_len(rest, true);
+        match &rest[..atom] {
+            tag if tag.starts_with('<') && atom > 1 => (),
+            entity if entity.starts_with('&') => res.push_str(unescape(entity)),
+            other => res.push_str(other),
+        }
+        rest = &rest[atom..];
+    }
+    res
+}
+
+/// SGR parameters of a tag, tags without them only keep the nesting
+fn ansi_style(name: &str) -> &'static str {
+    match name {
+        "b" | "strong" => "1",
+        "i" | "em" => "3",
+        "u" | "ins" | "a" => "4",
+        "s" | "strike" | "del" => "9",
+        "code" | "pre" => "36",
+        _ => "",
+    }
+}
+
+/// Message with tags replaced by ANSI escapes, for the terminal frontend
+///
+/// Example:
+/// ```
+/// # use qbitbot::bot::html::to_ansi;
+/// assert_eq!(to_ansi("<b>Tom &amp; Jerry</b>"), "\x1b[1mTom & Jerry\x1b[0m");
+/// ```
+pub fn to_ansi(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut open: Vec<(&str, &str)> = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let atom = atom_len(rest, true);
+        match &rest[..atom] {
+            tag if tag.starts_with("</") => {
+                let name = tag[2..].trim_end_matches('>');
+                if let Some(pos) = open.iter().rposition(|(opened, _)| *opened == name) {
+                    open.remove(pos);
+                    // a style can't be switched off alone, so the outer ones are restored
+                    res.push_str("\x1b[0m");
+                    for (_, style) in open.iter().filter(|(_, style)| !style.is_empty()) {
+                        res.push_str(&format!("\x1b[{}m", style));
+                    }
+                }
+            }
+            tag if tag.starts_with('<') && atom > 1 => {
+                let name = tag_name(tag);
+                let style = ansi_style(name);
+                if !style.is_empty() {
+                    res.push_str(&format!("\x1b[{}m", style));
+                }
+                open.push((name, style));
+            }
+            entity if entity.starts_with('&') => res.push_str(unescape(entity)),
+            other => res.push_str(other),
+        }
+        rest = &rest[atom..];
+    }
+    if !open.is_empty() {
+        res.push_str("\x1b[0m");
+    }
+    res
+}
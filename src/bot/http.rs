@@ -0,0 +1,61 @@
+//! Embedded HTTP server shared by the webhook, the API and metrics
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+
+#[async_trait]
+pub trait Handler: Send + Sync + 'static {
+    async fn handle(&self, req: Request<Body>) -> Response<Body>;
+}
+
+/// Compare secrets without returning early, so the time doesn't tell how much of
+/// a guess is right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub struct HttpServer {
+    incoming: AddrIncoming,
+    handler: Arc<dyn Handler>,
+}
+
+impl HttpServer {
+    /// Listen on `addr` before serving, so the caller knows the address. Port 0 picks
+    /// a free one.
+    pub fn bind(addr: &SocketAddr, handler: impl Handler) -> Result<Self> {
+        Ok(Self {
+            incoming: AddrIncoming::bind(addr)?,
+            handler: Arc::new(handler),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    /// Answer requests until `shutdown` completes, requests in progress are finished
+    pub async fn serve(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let handler = self.handler;
+        let make_svc = make_service_fn(move |_| {
+            let handler = handler.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let handler = handler.clone();
+                    async move { Ok::<_, Infallible>(handler.handle(req).await) }
+                }))
+            }
+        });
+        Server::builder(self.incoming)
+            .serve(make_svc)
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        Ok(())
+    }
+}
@@ -1,32 +1,197 @@
+use std::fmt::{self, Display};
 use std::time::Duration;
 
 use async_trait::async_trait;
-use fure::backoff::fixed;
-use fure::policies::{attempts, backoff};
-use rutebot::client::Rutebot;
-use rutebot::requests::SendMessage;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
 
 use crate::bot::qbot::MessageWrapper;
 
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// Command suggestion shown by Telegram clients
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BotCommand {
+    pub command: String,
+    pub description: String,
+}
+
+/// Chats that see a command list, more specific scopes win
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandScope {
+    AllPrivateChats,
+    AllGroupChats,
+    Chat { chat_id: i64 },
+}
+
+/// Messages of the bot, see [Outbox](super::outbox::Outbox) for the Telegram one
 #[async_trait]
 pub trait TelegramBackend: Sync + Send + 'static {
     async fn send_message(&self, chat_id: i64, message: MessageWrapper);
+
+    /// Message that may be merged with other notifications waiting for the chat
+    async fn send_notification(&self, chat_id: i64, message: MessageWrapper) {
+        self.send_message(chat_id, message).await
+    }
+
+    /// Notification for `chat_id` that is sent to `fallback` if the chat is unavailable.
+    /// Backends that don't know about delivery always use `chat_id`.
+    async fn send_notification_or(&self, chat_id: i64, _fallback: i64, message: MessageWrapper) {
+        self.send_notification(chat_id, message).await
+    }
+
+    /// Replace command suggestions for the scope, empty list hides them
+    async fn set_commands(&self, commands: Vec<BotCommand>, scope: CommandScope);
+
+    /// Wait until queued messages are sent
+    async fn flush(&self) {}
 }
 
+#[derive(Debug)]
+pub enum TelegramError {
+    /// Flood control, the request can be repeated after the delay
+    RetryAfter(Duration),
+    /// Message is malformed, too long, etc. Retry won't help
+    Permanent(String),
+    /// Bot is blocked or the chat is not found, no message can be sent to the chat
+    ChatUnavailable(String),
+    /// Network errors and Telegram server failures
+    Transient(String),
+}
+
+impl Display for TelegramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RetryAfter(delay) => write!(f, "Too many requests, retry after {:?}", delay),
+            Self::Permanent(reason) => write!(f, "Telegram rejected request: {}", reason),
+            Self::ChatUnavailable(reason) => write!(f, "Chat is unavailable: {}", reason),
+            Self::Transient(reason) => write!(f, "Telegram request failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TelegramError {}
+
+impl From<reqwest::Error> for TelegramError {
+    /// The URL contains the bot token, so it is left out of the logs
+    fn from(err: reqwest::Error) -> Self {
+        Self::Transient(err.without_url().to_string())
+    }
+}
+
+/// Bot API methods used for outgoing messages
 #[async_trait]
-impl TelegramBackend for Rutebot {
-    async fn send_message(&self, chat_id: i64, message: MessageWrapper) {
-        debug!("Sending message to chat({}): {:#?}", chat_id, message);
-        let send_msg = || async {
-            let reply = SendMessage {
-                parse_mode: message.parse_mode,
-                ..SendMessage::new(chat_id, &message.text)
-            };
-            self.prepare_api_request(reply).send().await
-        };
-        let policy = attempts(backoff(fixed(Duration::from_secs(3))), 3);
-        if fure::retry(send_msg, policy).await.is_err() {
-            error!("Failed to send reply 4 times")
+pub trait TelegramApi: Send + Sync + 'static {
+    async fn send_text(&self, chat_id: i64, text: &str, html: bool) -> Result<(), TelegramError>;
+
+    async fn set_commands(
+        &self,
+        commands: &[BotCommand],
+        scope: &CommandScope,
+    ) -> Result<(), TelegramError>;
+}
+
+/// Telegram Bot API client for outgoing requests. Rutebot only receives updates: its errors
+/// have no `retry_after` and it has no setMyCommands.
+pub struct TelegramBot {
+    token: String,
+    client: Client,
+}
+
+impl TelegramBot {
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            client: Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap(),
+        }
+    }
+
+    fn classify(code: u64, resp: &Value) -> TelegramError {
+        let description = resp
+            .get("description")
+            .and_then(Value::as_str)
+            .unwrap_or("no description")
+            .to_string();
+        let retry_after = resp
+            .pointer("/parameters/retry_after")
+            .and_then(Value::as_u64);
+        match (code, retry_after) {
+            (_, Some(secs)) => TelegramError::RetryAfter(Duration::from_secs(secs)),
+            (429, None) => TelegramError::RetryAfter(Duration::from_secs(1)),
+            (403, _) => TelegramError::ChatUnavailable(description),
+            (400, _) if description.contains("chat not found") => {
+                TelegramError::ChatUnavailable(description)
+            }
+            (400, _) | (401, _) | (404, _) => TelegramError::Permanent(description),
+            _ => TelegramError::Transient(description),
+        }
+    }
+
+    /// Ask Telegram to POST updates to `url` instead of answering getUpdates
+    pub async fn set_webhook(&self, url: &str, secret: &str) -> Result<(), TelegramError> {
+        let params = json!({ "url": url, "secret_token": secret });
+        self.call("setWebhook", &params).await?;
+        Ok(())
+    }
+
+    /// Webhook blocks getUpdates, so it is removed on shutdown and before polling
+    pub async fn delete_webhook(&self) -> Result<(), TelegramError> {
+        self.call("deleteWebhook", &json!({})).await?;
+        Ok(())
+    }
+
+    async fn call(&self, method: &str, params: &Value) -> Result<Value, TelegramError> {
+        let resp = self
+            .client
+            .post(format!("{}/bot{}/{}", TELEGRAM_API, self.token, method))
+            .json(params)
+            .send()
+            .await?;
+        let status = resp.status();
+        let resp: Value = match resp.json().await {
+            Ok(resp) => resp,
+            Err(_) if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+                Value::Null
+            }
+            Err(err) => return Err(err.into()),
         };
+        if resp.get("ok").and_then(Value::as_bool) == Some(true) {
+            Ok(resp.get("result").cloned().unwrap_or(Value::Null))
+        } else {
+            let code = resp
+                .get("error_code")
+                .and_then(Value::as_u64)
+                .unwrap_or_else(|| status.as_u16().into());
+            Err(Self::classify(code, &resp))
+        }
+    }
+}
+
+#[async_trait]
+impl TelegramApi for TelegramBot {
+    async fn send_text(&self, chat_id: i64, text: &str, html: bool) -> Result<(), TelegramError> {
+        debug!("Sending message to chat({}): {}", chat_id, text);
+        let mut params = json!({ "chat_id": chat_id, "text": text });
+        if html {
+            params["parse_mode"] = json!("HTML");
+        }
+        self.call("sendMessage", &params).await?;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    async fn set_commands(
+        &self,
+        commands: &[BotCommand],
+        scope: &CommandScope,
+    ) -> Result<(), TelegramError> {
+        debug!("Setting commands for {:?}: {:?}", scope, commands);
+        let params = json!({ "commands": commands, "scope": scope });
+        self.call("setMyCommands", &params).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+//! Prometheus metrics of the bot in the text exposition format.
+//!
+//! Counters are global, so torrent clients and the outbox record them without a handle to
+//! the bot. Gauges are read from [QbitBot] when `/metrics` is scraped.
+//!
+//! The same server answers `/healthz` for supervisors, see [health].
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::Value;
+
+use crate::bot::config::MetricsConfig;
+use crate::bot::health;
+use crate::bot::http::{Handler, HttpServer};
+use crate::bot::qbot::QbitBot;
+
+/// Upper bounds of torrent client latency buckets in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Clone, Default)]
+struct Histogram {
+    /// Not cumulative, summed up on rendering
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if let Some(pos) = BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[pos] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+struct Registry {
+    /// Command and outcome to count
+    updates: BTreeMap<(String, String), u64>,
+    /// Torrent client endpoint to latency
+    requests: BTreeMap<String, Histogram>,
+    request_errors: BTreeMap<String, u64>,
+    /// Reason to count
+    telegram_failures: BTreeMap<String, u64>,
+    telegram_retries: u64,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    updates: BTreeMap::new(),
+    requests: BTreeMap::new(),
+    request_errors: BTreeMap::new(),
+    telegram_failures: BTreeMap::new(),
+    telegram_retries: 0,
+});
+
+/// `outcome` is ok, forbidden, failed or unreachable
+pub fn count_update(command: &str, outcome: &str) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let key = (command.to_string(), outcome.to_string());
+    *registry.updates.entry(key).or_default() += 1;
+}
+
+/// Latency of a torrent client request, e.g. `/torrents/info` or `torrent-get`
+pub fn observe_request(endpoint: &str, elapsed: Duration, ok: bool) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry
+        .requests
+        .entry(endpoint.to_string())
+        .or_default()
+        .observe(elapsed.as_secs_f64());
+    if !ok {
+        *registry
+            .request_errors
+            .entry(endpoint.to_string())
+            .or_default() += 1;
+    }
+}
+
+/// Failed attempt to send a message, `retry` is set if it's sent again
+pub fn count_telegram_failure(reason: &str, retry: bool) {
+    let mut registry = REGISTRY.lock().unwrap();
+    *registry
+        .telegram_failures
+        .entry(reason.to_string())
+        .or_default() += 1;
+    if retry {
+        registry.telegram_retries += 1;
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn render_counters(out: &mut String) {
+    let registry = REGISTRY.lock().unwrap();
+    let name = "qbitbot_updates_total";
+    header(
+        out,
+        name,
+        "counter",
+        "Telegram updates by command and outcome",
+    );
+    for ((command, outcome), count) in &registry.updates {
+        writeln!(
+            out,
+            "{}{{command=\"{}\",outcome=\"{}\"}} {}",
+            name,
+            escape(command),
+            outcome,
+            count
+        )
+        .unwrap();
+    }
+
+    let name = "qbitbot_torrent_client_request_duration_seconds";
+    header(out, name, "histogram", "Torrent client request latency");
+    for (endpoint, histogram) in &registry.requests {
+        let endpoint = escape(endpoint);
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+            cumulative += count;
+            writeln!(
+                out,
+                "{}_bucket{{endpoint=\"{}\",le=\"{}\"}} {}",
+                name, endpoint, bound, cumulative
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "{}_bucket{{endpoint=\"{}\",le=\"+Inf\"}} {}",
+            name, endpoint, histogram.count
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}_sum{{endpoint=\"{}\"}} {}",
+            name, endpoint, histogram.sum
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{}_count{{endpoint=\"{}\"}} {}",
+            name, endpoint, histogram.count
+        )
+        .unwrap();
+    }
+
+    let name = "qbitbot_torrent_client_errors_total";
+    header(out, name, "counter", "Failed torrent client requests");
+    for (endpoint, count) in &registry.request_errors {
+        writeln!(
+            out,
+            "{}{{endpoint=\"{}\"}} {}",
+            name,
+            escape(endpoint),
+            count
+        )
+        .unwrap();
+    }
+
+    let name = "qbitbot_telegram_send_failures_total";
+    header(out, name, "counter", "Failed attempts to send a message");
+    for (reason, count) in &registry.telegram_failures {
+        writeln!(out, "{}{{reason=\"{}\"}} {}", name, reason, count).unwrap();
+    }
+    let name = "qbitbot_telegram_send_retries_total";
+    header(out, name, "counter", "Messages sent again after a failure");
+    writeln!(out, "{} {}", name, registry.telegram_retries).unwrap();
+}
+
+/// Speeds and counts by state of every reachable instance
+async fn render_torrents(out: &mut String, qbot: &QbitBot) {
+    let mut speeds = Vec::new();
+    let mut states = Vec::new();
+    for client in qbot.instances() {
+        let state = match client.sync().await {
+            Ok(state) => state,
+            Err(err) => {
+                warn!("Failed to get metrics of {}: {:#}", client.name(), err);
+                continue;
+            }
+        };
+        let name = escape(client.name());
+        let speed = |field: &str| -> u64 {
+            state
+                .server_state()
+                .get(field)
+                .and_then(Value::as_u64)
+                .unwrap_or_default()
+        };
+        speeds.push((name.clone(), speed("dl_info_speed"), speed("up_info_speed")));
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for torrent in state.torrents() {
+            let torrent_state = torrent.get("state").and_then(Value::as_str);
+            *counts
+                .entry(torrent_state.unwrap_or("unknown").to_string())
+                .or_default() += 1;
+        }
+        states.push((name, counts));
+    }
+
+    let name = "qbitbot_download_speed_bytes";
+    header(out, name, "gauge", "Total download speed of the instance");
+    for (instance, dl, _) in &speeds {
+        writeln!(out, "{}{{instance=\"{}\"}} {}", name, instance, dl).unwrap();
+    }
+    let name = "qbitbot_upload_speed_bytes";
+    header(out, name, "gauge", "Total upload speed of the instance");
+    for (instance, _, up) in &speeds {
+        writeln!(out, "{}{{instance=\"{}\"}} {}", name, instance, up).unwrap();
+    }
+    let name = "qbitbot_torrents";
+    header(out, name, "gauge", "Torrents of the instance by state");
+    for (instance, counts) in &states {
+        for (state, count) in counts {
+            writeln!(
+                out,
+                "{}{{instance=\"{}\",state=\"{}\"}} {}",
+                name,
+                instance,
+                escape(state),
+                count
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// All metrics, torrent client gauges only if `torrents` is set
+pub async fn render(qbot: &QbitBot, torrents: bool) -> String {
+    let mut out = String::new();
+    render_counters(&mut out);
+    let name = "qbitbot_active_chats";
+    header(&mut out, name, "gauge", "Chats with updates in progress");
+    writeln!(out, "{} {}", name, qbot.active_chats()).unwrap();
+    let name = "qbitbot_active_watchers";
+    header(&mut out, name, "gauge", "Torrents watched for completion");
+    writeln!(out, "{} {}", name, qbot.active_watchers()).unwrap();
+    if torrents {
+        render_torrents(&mut out, qbot).await;
+    }
+    out
+}
+
+struct MetricsHandler {
+    torrents: bool,
+    qbot: Arc<QbitBot>,
+}
+
+#[async_trait]
+impl Handler for MetricsHandler {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        match (req.method(), req.uri().path()) {
+            (&Method::GET, "/metrics") => Response::builder()
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Body::from(render(&self.qbot, self.torrents).await))
+                .unwrap(),
+            (&Method::GET, "/healthz") => health::respond(&self.qbot),
+            _ => {
+                let mut resp = Response::new(Body::from("Not Found"));
+                *resp.status_mut() = StatusCode::NOT_FOUND;
+                resp
+            }
+        }
+    }
+}
+
+/// Server of `/metrics` and `/healthz`
+pub fn bind(config: &MetricsConfig, qbot: Arc<QbitBot>) -> Result<HttpServer> {
+    let handler = MetricsHandler {
+        torrents: config.torrents,
+        qbot,
+    };
+    HttpServer::bind(&config.listen, handler)
+}
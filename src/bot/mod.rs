@@ -1,9 +1,34 @@
 pub const TAG_NAME: &str = "qbitbot";
 
+/// Tag of torrents added by `user`, e.g. `qbitbot:alice`
+pub fn owner_tag(user: &str) -> String {
+    format!("{}:{}", TAG_NAME, user)
+}
+
+/// User of [owner_tag] in comma separated `tags`
+pub fn tag_owner(tags: &str) -> Option<&str> {
+    tags.split(',')
+        .find_map(|tag| tag.trim().strip_prefix(TAG_NAME)?.strip_prefix(':'))
+}
+
+pub mod api;
+pub mod audit;
+pub mod backend;
+pub mod cli;
 pub mod commands;
 pub mod config;
+pub mod health;
+pub mod html;
+pub mod http;
 pub mod messages;
+pub mod metrics;
 mod notifier;
+pub mod outbox;
 pub mod qb_chat;
 pub mod qb_client;
 pub mod qbot;
+pub mod sync;
+pub mod systemd;
+pub mod terminal;
+pub mod watchers;
+pub mod webhook;
@@ -7,25 +7,42 @@ use super::qbot::MessageWrapper;
 use std::sync::Arc;
 
 pub enum CheckType {
-    /// Completed(name) check that torrent named 'name' is completed
-    Completed(String),
+    /// Check that torrent named `name` of `instance` is completed.
+    /// Instance is `None` when there is only one of them
+    Completed {
+        name: String,
+        instance: Option<String>,
+    },
 }
 
 pub trait Notifier {
-    fn create_notifier_tx(rbot: Arc<dyn TelegramBackend>, chat_id: i64) -> Sender<CheckType> {
+    /// Notification goes to `chat_id`, or to `fallback_chat_id` if the chat is unavailable
+    fn create_notifier_tx(
+        rbot: Arc<dyn TelegramBackend>,
+        chat_id: i64,
+        fallback_chat_id: Option<i64>,
+    ) -> Sender<CheckType> {
         let (tx, rx) = oneshot::channel();
         tokio::spawn(async move {
             if let Ok(check) = rx.await {
                 let send_text = match check {
-                    CheckType::Completed(name) => {
-                        format!("{} is done", name)
-                    }
+                    CheckType::Completed {
+                        name,
+                        instance: None,
+                    } => format!("{} is done", name),
+                    CheckType::Completed {
+                        name,
+                        instance: Some(instance),
+                    } => format!("[{}] {} is done", instance, name),
                 };
                 let message = MessageWrapper {
                     text: send_text,
                     parse_mode: None,
                 };
-                rbot.send_message(chat_id, message).await;
+                match fallback_chat_id {
+                    Some(fallback) => rbot.send_notification_or(chat_id, fallback, message).await,
+                    None => rbot.send_notification(chat_id, message).await,
+                }
             }
         });
         tx
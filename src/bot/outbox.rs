@@ -0,0 +1,274 @@
+//! Outgoing message queue respecting Telegram flood limits.
+//!
+//! Telegram allows about 30 messages per second overall and one per second in a chat,
+//! so messages wait in per-chat queues and are sent one at a time.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rutebot::requests::ParseMode;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+
+use crate::bot::html;
+use crate::bot::messages::{BotCommand, CommandScope, TelegramApi, TelegramBackend, TelegramError};
+use crate::bot::metrics;
+use crate::bot::qbot::MessageWrapper;
+
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Delay between any two messages
+    pub global: Duration,
+    /// Delay between two messages of a chat
+    pub chat: Duration,
+    /// Attempts to send a message on network and server errors
+    pub attempts: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            global: Duration::from_millis(35),
+            chat: Duration::from_secs(1),
+            attempts: 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Outgoing {
+    chat_id: i64,
+    text: String,
+    html: bool,
+    notification: bool,
+    /// Chat that gets the message if `chat_id` is unavailable
+    fallback: Option<i64>,
+    attempts: u32,
+}
+
+enum Request {
+    Send(Outgoing),
+    /// Answered when all queued messages are sent
+    Flush(oneshot::Sender<()>),
+}
+
+struct ChatQueue {
+    parts: VecDeque<Outgoing>,
+    /// The chat can't get messages until then
+    next: Instant,
+}
+
+/// [TelegramBackend] that queues messages and sends them in background
+pub struct Outbox<A: TelegramApi> {
+    api: Arc<A>,
+    tx: mpsc::UnboundedSender<Request>,
+}
+
+impl<A: TelegramApi> Outbox<A> {
+    pub fn new(api: A) -> Self {
+        Self::with_limits(api, Limits::default())
+    }
+
+    pub fn with_limits(api: A, limits: Limits) -> Self {
+        let api = Arc::new(api);
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(Self::run(api.clone(), limits, rx));
+        Self { api, tx }
+    }
+
+    fn enqueue(
+        &self,
+        chat_id: i64,
+        message: MessageWrapper,
+        notification: bool,
+        fallback: Option<i64>,
+    ) {
+        let is_html = matches!(message.parse_mode, Some(ParseMode::Html));
+        for text in html::split(&message.text, html::MESSAGE_LIMIT, is_html) {
+            let outgoing = Outgoing {
+                chat_id,
+                text,
+                html: is_html,
+                notification,
+                fallback,
+                attempts: 0,
+            };
+            if self.tx.send(Request::Send(outgoing)).is_err() {
+                error!(
+                    "Outgoing queue is stopped, message to chat({}) is lost",
+                    chat_id
+                );
+            }
+        }
+    }
+
+    /// Put message to the chat queue, notifications are merged with the last waiting one
+    fn push(queues: &mut HashMap<i64, ChatQueue>, outgoing: Outgoing) {
+        let queue = queues.entry(outgoing.chat_id).or_insert_with(|| ChatQueue {
+            parts: VecDeque::new(),
+            next: Instant::now(),
+        });
+        if let Some(last) = queue.parts.back_mut() {
+            let fits =
+                last.text.chars().count() + outgoing.text.chars().count() < html::MESSAGE_LIMIT;
+            let same = last.html == outgoing.html && last.fallback == outgoing.fallback;
+            if outgoing.notification && last.notification && same && fits {
+                last.text.push('\n');
+                last.text.push_str(&outgoing.text);
+                return;
+            }
+        }
+        queue.parts.push_back(outgoing);
+    }
+
+    fn take(
+        queues: &mut HashMap<i64, ChatQueue>,
+        flushes: &mut Vec<oneshot::Sender<()>>,
+        request: Request,
+    ) {
+        match request {
+            Request::Send(outgoing) => Self::push(queues, outgoing),
+            Request::Flush(done) => flushes.push(done),
+        }
+    }
+
+    /// Chat with waiting messages that can get them first
+    fn next_chat(queues: &HashMap<i64, ChatQueue>) -> Option<(i64, Instant)> {
+        queues
+            .iter()
+            .filter(|(_, queue)| !queue.parts.is_empty())
+            .min_by_key(|(_, queue)| queue.next)
+            .map(|(chat_id, queue)| (*chat_id, queue.next))
+    }
+
+    async fn run(api: Arc<A>, limits: Limits, mut rx: mpsc::UnboundedReceiver<Request>) {
+        let mut queues = HashMap::new();
+        let mut flushes = Vec::new();
+        let mut global_next = Instant::now();
+        let mut closed = false;
+        loop {
+            while let Ok(request) = rx.try_recv() {
+                Self::take(&mut queues, &mut flushes, request);
+            }
+            let (chat_id, chat_next) = match Self::next_chat(&queues) {
+                Some(next) => next,
+                None => {
+                    for done in flushes.drain(..) {
+                        done.send(()).ok();
+                    }
+                    if closed {
+                        return;
+                    }
+                    match rx.recv().await {
+                        Some(request) => Self::take(&mut queues, &mut flushes, request),
+                        None => closed = true,
+                    }
+                    continue;
+                }
+            };
+            let at = chat_next.max(global_next);
+            if at > Instant::now() {
+                // a message for another chat may be sent earlier
+                tokio::select! {
+                    _ = sleep_until(at) => (),
+                    request = rx.recv(), if !closed => {
+                        match request {
+                            Some(request) => Self::take(&mut queues, &mut flushes, request),
+                            None => closed = true,
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let queue = queues.get_mut(&chat_id).unwrap();
+            let mut outgoing = queue.parts.pop_front().unwrap();
+            let result = api.send_text(chat_id, &outgoing.text, outgoing.html).await;
+            let now = Instant::now();
+            global_next = now + limits.global;
+            queue.next = now + limits.chat;
+            match result {
+                Ok(()) => (),
+                Err(TelegramError::RetryAfter(delay)) => {
+                    metrics::count_telegram_failure("flood", true);
+                    warn!("Flood control for chat({}), waiting {:?}", chat_id, delay);
+                    queue.next = now + delay;
+                    queue.parts.push_front(outgoing);
+                }
+                Err(TelegramError::Transient(err)) => {
+                    outgoing.attempts += 1;
+                    let retry = outgoing.attempts < limits.attempts;
+                    metrics::count_telegram_failure("transient", retry);
+                    if retry {
+                        warn!("Failed to send message to chat({}): {}", chat_id, err);
+                        queue.next = now + limits.chat * outgoing.attempts;
+                        queue.parts.push_front(outgoing);
+                    } else {
+                        error!(
+                            "Failed to send message to chat({}) {} times: {}",
+                            chat_id, outgoing.attempts, err
+                        );
+                    }
+                }
+                Err(TelegramError::Permanent(err)) => {
+                    metrics::count_telegram_failure("permanent", false);
+                    error!("Dropping message to chat({}): {}", chat_id, err);
+                }
+                Err(TelegramError::ChatUnavailable(err)) => {
+                    metrics::count_telegram_failure("chat_unavailable", false);
+                    let (redirected, dropped): (Vec<_>, Vec<_>) = std::iter::once(outgoing)
+                        .chain(queue.parts.drain(..))
+                        .partition(|outgoing| outgoing.fallback.is_some());
+                    if !dropped.is_empty() {
+                        error!(
+                            "Dropping {} messages to chat({}): {}",
+                            dropped.len(),
+                            chat_id,
+                            err
+                        );
+                    }
+                    for mut outgoing in redirected {
+                        let fallback = outgoing.fallback.take().unwrap();
+                        warn!(
+                            "Chat({}) is unavailable, sending to chat({}): {}",
+                            chat_id, fallback, err
+                        );
+                        outgoing.chat_id = fallback;
+                        outgoing.attempts = 0;
+                        Self::push(&mut queues, outgoing);
+                    }
+                }
+            }
+            queues.retain(|_, queue| !queue.parts.is_empty() || queue.next > now);
+        }
+    }
+}
+
+#[async_trait]
+impl<A: TelegramApi> TelegramBackend for Outbox<A> {
+    async fn send_message(&self, chat_id: i64, message: MessageWrapper) {
+        self.enqueue(chat_id, message, false, None)
+    }
+
+    async fn send_notification(&self, chat_id: i64, message: MessageWrapper) {
+        self.enqueue(chat_id, message, true, None)
+    }
+
+    async fn send_notification_or(&self, chat_id: i64, fallback: i64, message: MessageWrapper) {
+        self.enqueue(chat_id, message, true, Some(fallback))
+    }
+
+    async fn set_commands(&self, commands: Vec<BotCommand>, scope: CommandScope) {
+        if let Err(err) = self.api.set_commands(&commands, &scope).await {
+            warn!("Failed to set commands for {:?}: {}", scope, err);
+        }
+    }
+
+    async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(Request::Flush(done_tx)).is_ok() {
+            done_rx.await.ok();
+        }
+    }
+}
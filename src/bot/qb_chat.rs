@@ -1,63 +1,59 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
 use itertools::Itertools;
 
+use crate::bot::audit::{Actor, AuditEntry, AuditLog};
 use crate::bot::commands::download::QDownloadAction;
+use crate::bot::commands::limit::QLimitAction;
+use crate::bot::commands::list::{QListAction, QbListRecord};
 use crate::bot::commands::pause_resume::QPauseResumeAction;
+use crate::bot::commands::registry::{
+    self, CommandKind, CommandSpec, MenuContext, ParseError, ParsedCommand,
+};
 use crate::bot::commands::QbCommandAction;
 use crate::bot::commands::simple::QHelp;
+use crate::bot::config::{DisplayConfig, Role};
+use crate::bot::html;
 use crate::bot::messages::TelegramBackend;
 use crate::bot::notifier::Notifier;
 use crate::bot::qb_chat::MenuValue::*;
 use crate::bot::qb_client::QbClient;
-use crate::bot::qbot::MessageWrapper;
+use crate::bot::qbot::{MessageWrapper, NOT_OWNER_MESSAGE, UNREACHABLE_MESSAGE};
+use crate::bot::watchers::Watchers;
+
+/// Entries shown by `/audit` without a number
+const AUDIT_COUNT: usize = 10;
 
 #[derive(Clone, Debug, PartialOrd, Ord, Eq, PartialEq)]
 pub enum MenuValue {
     Main,
     Help,
     List,
+    ListAll,
+    Mine,
     Download,
+    Instance,
     TorrentPage(usize),
     Pause,
     Resume,
 }
 
-pub static COMMANDS: &[MenuValue] = &[Main, Help, List, Download];
-
 impl MenuValue {
     pub fn get_command(&self) -> &str {
         match self {
             Main => "/main",
             Help => "/help",
             List => "/list",
+            ListAll => "/list all",
+            Mine => "/mine",
             Download => "/download",
+            Instance => "/instance",
             TorrentPage(_) => "/torrent",
             Pause => "/pause",
-            Resume => "/resume,",
-        }
-    }
-
-    pub fn get_help(&self) -> &str {
-        match self {
-            Main => "Go to main menu",
-            Help => "Show help for all commands",
-            List => "List torrents",
-            Download => "Start downloading by link or attached file",
-            TorrentPage(_) => "Show torrent page",
-            _ => "",
+            Resume => "/resume",
         }
     }
-
-    pub fn generate_cmds() -> HashMap<String, MenuValue> {
-        COMMANDS
-            .iter()
-            .cloned()
-            .map(|v| (v.get_command().to_string(), v))
-            .collect()
-    }
 }
 
 #[derive(Clone, Debug)]
@@ -73,14 +69,14 @@ impl From<MenuValue> for MenuTree {
             Main => MenuTree {
                 value,
                 parent: None,
-                children: vec![Help, List, Download],
+                children: vec![Help, List, Download, Instance],
             },
             Help => MenuTree {
                 value,
                 parent: Some(Main),
                 children: vec![],
             },
-            List => MenuTree {
+            List | ListAll | Mine | Instance => MenuTree {
                 value,
                 ..MenuTree::from(Help)
             },
@@ -128,33 +124,181 @@ impl MenuTree {
 pub struct QbChat {
     chat_id: i64,
     menu_pos: MenuTree,
-    qbclient: QbClient,
-    commands_map: HashMap<String, MenuValue>,
+    instances: Vec<Arc<QbClient>>,
+    active: usize,
+    /// Filter and sort of the last /list command
+    list_filter: Option<String>,
+    list_sort: Option<String>,
+    watchers: Arc<Watchers>,
+    display: DisplayConfig,
+    audit: Arc<AuditLog>,
+    /// Sender of the current message, group chats have many
+    actor: Actor,
+    /// The sender can change only torrents they added
+    own_only: bool,
+    /// Role of the sender, /help lists only commands it allows
+    role: Role,
 }
 
 impl QbChat {
-    pub fn new(chat_id: i64, qbclient: QbClient) -> Self {
+    /// `instances` must not be empty, the first one is active
+    pub fn new(
+        chat_id: i64,
+        instances: Vec<Arc<QbClient>>,
+        watchers: Arc<Watchers>,
+        audit: Arc<AuditLog>,
+    ) -> Self {
         Self {
             chat_id,
-            qbclient,
+            instances,
+            watchers,
+            active: 0,
             menu_pos: MenuTree::from(Main),
-            commands_map: MenuValue::generate_cmds(),
+            list_filter: None,
+            list_sort: None,
+            display: DisplayConfig::default(),
+            audit,
+            actor: Actor {
+                chat_id: Some(chat_id),
+                ..Actor::default()
+            },
+            own_only: false,
+            role: Role::Viewer,
         }
     }
 
+    pub fn set_display(&mut self, display: &DisplayConfig) {
+        self.display = display.clone();
+    }
+
+    pub fn set_actor(&mut self, actor: Actor) {
+        self.actor = actor;
+    }
+
+    pub fn set_own_only(&mut self, own_only: bool) {
+        self.own_only = own_only;
+    }
+
+    pub fn set_role(&mut self, role: Role) {
+        self.role = role;
+    }
+
+    /// Username tagged on added torrents, chats without a sender add them untagged
+    fn owner(&self) -> Option<&str> {
+        Some(self.actor.username.as_str()).filter(|username| !username.is_empty())
+    }
+
+    /// Command that `text` runs in the current menu, free text in the download menu is a link
+    pub fn command_spec(&self, text: &str) -> Option<&'static CommandSpec> {
+        match registry::parse(text) {
+            Ok(command) => Some(command.spec),
+            Err(ParseError::Usage { spec, .. }) => Some(spec),
+            Err(ParseError::NotCommand) if self.menu_pos.value == Download => {
+                registry::find("download")
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn qbclient(&self) -> &Arc<QbClient> {
+        &self.instances[self.active]
+    }
+
+    /// Name of the active instance if there is a choice
+    fn instance_label(&self) -> Option<String> {
+        if self.instances.len() > 1 {
+            Some(self.qbclient().name().to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Apply filter and sort of the last /list command
+    fn filter_sort(&self, client: &QbClient, list: QListAction) -> QListAction {
+        list.filter_sort(
+            self.list_filter.as_deref(),
+            self.list_sort
+                .as_deref()
+                .or(self.display.list_sort.as_deref()),
+            |state| client.backend().is_paused(state),
+        )
+    }
+
+    fn list_header(&self, num: usize) -> String {
+        let name = html::escape(self.instances[num].name());
+        format!("<b>{}</b> /instance{}\n", name, num)
+    }
+
+    async fn list_all(&self) -> String {
+        let mut res = Vec::new();
+        for (num, client) in self.instances.iter().enumerate() {
+            let list = match client.get_cached_list().await {
+                Ok(list) => self.filter_sort(client, list).action_result_to_string(),
+                Err(err) => {
+                    warn!("Failed to get list of {}: {:#}", client.name(), err);
+                    client.spawn_reconnect();
+                    String::from(UNREACHABLE_MESSAGE)
+                }
+            };
+            res.push(format!("{}{}", self.list_header(num), list));
+        }
+        res.join("\n\n")
+    }
+
+    fn show_instances(&self) -> String {
+        self.instances
+            .iter()
+            .enumerate()
+            .map(|(num, client)| {
+                let active = if num == self.active { " (active)" } else { "" };
+                format!("/instance{} {}{}", num, html::escape(client.name()), active)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     async fn do_cmd(&mut self) -> Result<String> {
         let res = match self.menu_pos.value {
             Main => "Main menu".to_string(),
-            Help => QHelp {}.action_result_to_string(),
-            List => self
-                .qbclient
-                .get_cached_list()
-                .await?
-                .action_result_to_string(),
+            Help => QHelp { role: self.role }.action_result_to_string(),
+            List => {
+                let list = self.qbclient().get_cached_list().await?;
+                let list = self.filter_sort(self.qbclient(), list);
+                let header = match self.instance_label() {
+                    Some(_) => self.list_header(self.active),
+                    None => String::new(),
+                };
+                format!("{}{}", header, list.action_result_to_string())
+            }
+            ListAll => self.list_all().await,
+            Mine => {
+                let client = self.qbclient();
+                let list = client
+                    .get_cached_list()
+                    .await?
+                    .owned_by(&self.actor.username);
+                list.filter_sort(None, self.display.list_sort.as_deref(), |state| {
+                    client.backend().is_paused(state)
+                })
+                .action_result_to_string()
+            }
             Download => "Send torrent link or attach torrent file".to_string(),
+            Instance => self.show_instances(),
             TorrentPage(id) => {
-                if let Some(record) = self.qbclient.get_cached_list().await?.get_record_by_num(id) {
-                    record.to_string()
+                let client = self.qbclient();
+                if let Some(record) = client.get_cached_list().await?.get_record_by_num(id) {
+                    // the page is still useful without details
+                    match client.backend().properties(&record.get_hash()).await {
+                        Ok(properties) => format!("{}\n{}", record, record.details(&properties)),
+                        Err(err) => {
+                            warn!(
+                                "Failed to get properties of {}: {:#}",
+                                record.get_hash(),
+                                err
+                            );
+                            record.to_string()
+                        }
+                    }
                 } else {
                     "There is no torrent with this id".to_string()
                 }
@@ -165,58 +309,191 @@ impl QbChat {
     }
 
     pub async fn select_goto(&mut self, rbot: Arc<dyn TelegramBackend>, text: &str) -> Result<()> {
-        match text {
-            "/back" => self.back(rbot).await?,
-            command if self.commands_map.contains_key(command) => {
-                self.goto(rbot, self.commands_map.get(command).unwrap().to_owned())
-                    .await?
+        match registry::parse(text) {
+            Ok(command) => self.run(rbot, command).await,
+            Err(ParseError::NotCommand) if self.menu_pos.value == Download => {
+                self.download(rbot, text).await
+            }
+            Err(ParseError::NotCommand) | Err(ParseError::Unknown(_)) => {
+                self.goto(rbot, self.get_menu_pos()).await
+            }
+            Err(err) => {
+                self.send_plain(rbot, err.to_string()).await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn run(&mut self, rbot: Arc<dyn TelegramBackend>, command: ParsedCommand) -> Result<()> {
+        match command.kind() {
+            CommandKind::Main => self.goto(rbot, Main).await,
+            CommandKind::Help => self.goto(rbot, Help).await,
+            CommandKind::Mine => self.goto(rbot, Mine).await,
+            CommandKind::Download => self.goto(rbot, Download).await,
+            CommandKind::Back => self.back(rbot).await,
+            // QbitBot runs it, chats don't own the config
+            CommandKind::Reload => Ok(()),
+            CommandKind::Audit => {
+                let entries = self.audit.recent(command.count().unwrap_or(AUDIT_COUNT))?;
+                let text = if entries.is_empty() {
+                    String::from("Audit log is empty")
+                } else {
+                    entries
+                        .iter()
+                        .map(AuditEntry::summary)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                self.send_plain(rbot, text).await;
+                Ok(())
             }
-            _ if text.starts_with("/torrent") => {
-                if let Ok(id) = text.strip_prefix("/torrent").unwrap().parse::<usize>() {
-                    self.goto(rbot, TorrentPage(id)).await?
+            CommandKind::List => {
+                let filter = command.get("filter");
+                self.list_sort = command.get("sort").map(String::from);
+                if filter == Some("all") {
+                    self.list_filter = None;
+                    self.goto(rbot, ListAll).await
                 } else {
-                    self.goto(rbot, self.menu_pos.value.clone()).await?
+                    self.list_filter = filter.map(String::from);
+                    self.goto(rbot, List).await
+                }
+            }
+            CommandKind::Instance => match command.id() {
+                Some(num) if num >= self.instances.len() => {
+                    self.send_plain(rbot, String::from("There is no instance with this id"))
+                        .await;
+                    Ok(())
                 }
+                Some(num) => {
+                    self.active = num;
+                    self.goto(rbot, Instance).await
+                }
+                None => self.goto(rbot, Instance).await,
+            },
+            CommandKind::Torrent => {
+                let id = command.id().unwrap_or_default();
+                self.goto(rbot, TorrentPage(id)).await
             }
-            cmd @ ("/pause" | "/resume") if matches!(self.menu_pos.value, TorrentPage(_)) => {
-                let res = if let TorrentPage(id) = self.menu_pos.value {
-                    QPauseResumeAction::new(cmd.strip_prefix('/').unwrap())
-                        .act(&mut self.qbclient, id)
+            CommandKind::Pause | CommandKind::Resume | CommandKind::Limit => {
+                let id = match (command.id(), &command.spec.context, &self.menu_pos.value) {
+                    (Some(id), _, _) => id,
+                    (None, MenuContext::TorrentPage, TorrentPage(id)) => *id,
+                    _ => {
+                        let err = ParseError::usage(command.spec, "Open torrent page or pass id");
+                        self.send_plain(rbot, err.to_string()).await;
+                        return Ok(());
+                    }
+                };
+                // the hash is resolved once, so the checked torrent is the changed one
+                let list = self.qbclient().get_cached_list().await?;
+                let record = match list.get_record_by_num(id) {
+                    Some(record) => record,
+                    None => {
+                        self.send_plain(rbot, String::from("There is no torrent with this id"))
+                            .await;
+                        return Ok(());
+                    }
+                };
+                if self.own_only && !record.is_owned_by(&self.actor.username) {
+                    self.send_plain(rbot, String::from(NOT_OWNER_MESSAGE)).await;
+                    return Ok(());
+                }
+                let hash = record.get_hash();
+                let mut entry = self.audit_entry(command.spec.name, &record);
+                let res = if command.kind() == CommandKind::Limit {
+                    for key in ["dl", "up"].iter() {
+                        if let Some(value) = command.get(key) {
+                            entry = entry.param(key, value);
+                        }
+                    }
+                    let (dl, up) = (command.speed("dl"), command.speed("up"));
+                    QLimitAction::act_hash(self.qbclient(), &hash, dl, up)
                         .await
                         .action_result_to_string()
                 } else {
-                    // dummy code to complete if let else
-                    String::from("")
-                };
-                let message = MessageWrapper {
-                    text: res,
-                    parse_mode: None,
+                    QPauseResumeAction::new(command.spec.name)
+                        .act_hash(self.qbclient(), &hash)
+                        .await
+                        .action_result_to_string()
                 };
-                rbot.send_message(self.chat_id, message).await
+                self.audit.record(entry.outcome(&res));
+                self.send_plain(rbot, res).await;
+                Ok(())
             }
-            _ => match self.menu_pos.value {
-                Download => {
-                    let download_obj = QDownloadAction::default()
-                        .send_link(&self.qbclient, text)
-                        .await?;
-                    let tx = Self::create_notifier_tx(rbot.clone(), self.chat_id);
-                    download_obj.create_notifier(&mut self.qbclient, tx).await;
-                    let res = download_obj.action_result_to_string();
-                    let message = MessageWrapper {
-                        text: res,
-                        parse_mode: Some(rutebot::requests::ParseMode::Html),
-                    };
-                    rbot.send_message(self.chat_id, message).await
-                }
-                _ => self.goto(rbot, self.menu_pos.value.clone()).await?,
-            },
+        }
+    }
+
+    /// Audit entry of a command on the torrent of the active instance
+    fn audit_entry(&self, command: &str, record: &QbListRecord) -> AuditEntry {
+        AuditEntry::new(&self.actor, command, self.qbclient())
+            .target(&record.get_hash(), Some(record.get_name()))
+    }
+
+    async fn download(&self, rbot: Arc<dyn TelegramBackend>, link: &str) -> Result<()> {
+        let entry = AuditEntry::new(&self.actor, "download", self.qbclient()).param("link", link);
+        let download_obj = match QDownloadAction::default()
+            .send_link(self.qbclient(), link, self.owner())
+            .await
+        {
+            Ok(download_obj) => download_obj,
+            Err(err) => {
+                self.audit.record(entry.outcome(format!("{:#}", err)));
+                return Err(err);
+            }
+        };
+        let entry = match download_obj.hash() {
+            Some(hash) => {
+                let name = QDownloadAction::get_name(self.qbclient(), hash).await;
+                entry.target(hash, name)
+            }
+            None => entry,
+        };
+        self.audit
+            .record(entry.outcome(download_obj.action_result_to_string()));
+        // the private chat of the adder, group chats don't need to know. The adder may
+        // have never started that chat, then the chat of the download is notified.
+        let (notify_chat_id, fallback_chat_id) = match self.actor.user_id {
+            Some(user_id) if user_id != self.chat_id => (user_id, Some(self.chat_id)),
+            _ => (self.chat_id, None),
         };
+        let tx = Self::create_notifier_tx(rbot.clone(), notify_chat_id, fallback_chat_id);
+        download_obj
+            .create_notifier(
+                self.qbclient(),
+                self.instance_label(),
+                notify_chat_id,
+                fallback_chat_id,
+                &self.watchers,
+                tx,
+            )
+            .await;
+        let message = MessageWrapper {
+            text: download_obj.action_result_to_string(),
+            parse_mode: Some(rutebot::requests::ParseMode::Html),
+        };
+        rbot.send_message(self.chat_id, message).await;
         Ok(())
     }
 
+    async fn send_plain(&self, rbot: Arc<dyn TelegramBackend>, text: String) {
+        let message = MessageWrapper {
+            text,
+            parse_mode: None,
+        };
+        rbot.send_message(self.chat_id, message).await
+    }
+
     async fn goto(&mut self, rbot: Arc<dyn TelegramBackend>, menu_value: MenuValue) -> Result<()> {
-        self.menu_pos = MenuTree::from(menu_value);
-        let content = self.do_cmd().await?;
+        let prev_pos = std::mem::replace(&mut self.menu_pos, MenuTree::from(menu_value));
+        // pages are large futures, boxed to keep them off the stack in debug builds
+        let content = match Box::pin(self.do_cmd()).await {
+            Ok(content) => content,
+            Err(err) => {
+                // stay where we were if the page can't be shown
+                self.menu_pos = prev_pos;
+                return Err(err);
+            }
+        };
         let text = self.menu_pos.show(content).await;
         let message = MessageWrapper {
             text,
@@ -236,8 +513,9 @@ impl QbChat {
         Ok(())
     }
 
-    pub async fn relogin(&mut self) -> Result<()> {
-        self.qbclient.login().await
+    /// Start background reconnection to the active instance
+    pub fn reconnect(&self) {
+        self.qbclient().spawn_reconnect()
     }
 
     pub fn get_menu_pos(&self) -> MenuValue {
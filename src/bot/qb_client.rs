@@ -1,111 +1,127 @@
-use std::time::Duration;
+use std::{
+    cmp::min,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::{anyhow, Context, Result};
-use reqwest::{
-    Client,
-    header::{HeaderMap, ORIGIN}, Response,
+use anyhow::Result;
+use tokio::{
+    sync::{Mutex, MutexGuard},
+    time::sleep,
 };
-use serde::Serialize;
 
 use super::{
-    commands::{
-        cmd_list::{Login, QTag},
-        list::QListAction,
-    },
-    config::QbConfig,
-    TAG_NAME,
+    backend::{self, TorrentBackend},
+    commands::list::QListAction,
+    config::InstanceConfig,
+    sync::SyncState,
 };
 
-#[derive(Debug, Clone)]
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Torrent client instance shared by all chats: one session and one synced state
 pub struct QbClient {
-    pub config: QbConfig,
-    client: Client,
-    cached_list: Option<QListAction>,
+    pub config: InstanceConfig,
+    backend: Box<dyn TorrentBackend>,
+    state: Mutex<SyncState>,
+    reconnecting: AtomicBool,
 }
 
 impl QbClient {
-    pub async fn new(config: &QbConfig) -> Self {
-        let headers = Self::gen_headers(config.location.clone());
-        let qbclient = QbClient {
-            client: Self::build_client(headers),
-            cached_list: None,
-            config: config.to_owned(),
-        };
-        qbclient.login().await.unwrap();
-        if qbclient.create_tag().await.is_err() {
-            error!("Failed to create tag for Qbitbot. Probably your qbittorrent version doesn't support it")
-        };
-        qbclient
+    pub async fn new(config: &InstanceConfig) -> Arc<Self> {
+        Self::with_backend(config, backend::from_config(config)).await
     }
 
-    fn gen_headers(origin: String) -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        headers.insert(ORIGIN, origin.parse().unwrap());
-        headers
+    pub async fn with_backend(
+        config: &InstanceConfig,
+        backend: Box<dyn TorrentBackend>,
+    ) -> Arc<Self> {
+        let qbclient = Self::build(config, backend);
+        qbclient.connect().await;
+        qbclient
     }
 
-    fn build_client(headers: HeaderMap) -> Client {
-        Client::builder()
-            .cookie_store(true)
-            .default_headers(headers)
-            .timeout(Duration::from_secs(10))
-            .build()
-            .unwrap()
+    /// Log in in background, so a slow instance doesn't hold the start. Requests made
+    /// before that log in on their own.
+    pub fn connect_later(config: &InstanceConfig) -> Arc<Self> {
+        let qbclient = Self::build(config, backend::from_config(config));
+        let client = qbclient.clone();
+        tokio::spawn(async move { client.connect().await });
+        qbclient
     }
 
-    pub async fn qpost<T: Serialize>(&self, location: &str, action: T) -> Result<Response> {
-        let mut api_loc = self.config.location.clone();
-        api_loc.push_str("/api/v2");
-        api_loc.push_str(location);
-        let resp = self
-            .client
-            .post(api_loc)
-            .form(&action)
-            .send()
-            .await
-            .with_context(|| "Failed to send POST request")?;
+    fn build(config: &InstanceConfig, backend: Box<dyn TorrentBackend>) -> Arc<Self> {
+        Arc::new(QbClient {
+            backend,
+            state: Mutex::new(SyncState::default()),
+            config: config.to_owned(),
+            reconnecting: AtomicBool::new(false),
+        })
+    }
 
-        if resp.status().is_success() {
-            trace!("{:#?}", resp);
-            Ok(resp)
-        } else {
-            Err(anyhow!(format!(
-                "Command failed. Status code: {}",
-                resp.status()
-            )))
+    async fn connect(self: &Arc<Self>) {
+        if let Err(err) = self.backend.connect().await {
+            error!("Failed to connect to {}: {:#}", self.name(), err);
+            self.spawn_reconnect();
         }
     }
 
-    pub async fn login(&self) -> Result<()> {
-        let login = Login {
-            username: self.config.user.clone(),
-            password: self.config.password.clone(),
-        };
-        self.qpost("/auth/login", login).await?;
-        Ok(())
+    pub fn name(&self) -> &str {
+        &self.config.name
     }
 
-    async fn create_tag(&self) -> Result<()> {
-        self.qpost(
-            "/torrents/createTags",
-            QTag {
-                tags: TAG_NAME.to_string(),
-            },
-        )
-        .await?;
-        Ok(())
+    pub fn backend(&self) -> &dyn TorrentBackend {
+        self.backend.as_ref()
     }
 
-    pub async fn get_cached_list(&mut self) -> Result<QListAction> {
-        if self.cached_list.is_some() {
-            let mut cached_list = self.cached_list.to_owned().unwrap();
-            cached_list.check_and_update(self).await?;
-            self.cached_list = Some(cached_list.clone());
-            Ok(cached_list)
-        } else {
-            let cached_list = QListAction::new(self).await?;
-            self.cached_list = Some(cached_list.to_owned());
-            Ok(cached_list)
+    /// Keep trying to connect in background with exponential backoff until it succeeds.
+    /// Does nothing if reconnection is already in progress.
+    pub fn spawn_reconnect(self: &Arc<Self>) {
+        if self.reconnecting.swap(true, Ordering::SeqCst) {
+            return;
         }
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut delay = RECONNECT_INITIAL_DELAY;
+            loop {
+                sleep(delay).await;
+                match client.backend.connect().await {
+                    Ok(()) => {
+                        info!("Reconnected to {}", client.name());
+                        break;
+                    }
+                    Err(err) => {
+                        delay = min(delay * 2, RECONNECT_MAX_DELAY);
+                        warn!(
+                            "{} is still unreachable: {:#}. Next try in {:?}",
+                            client.name(),
+                            err,
+                            delay
+                        );
+                    }
+                }
+            }
+            client.reconnecting.store(false, Ordering::SeqCst);
+        });
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnecting.load(Ordering::SeqCst)
+    }
+
+    /// Apply changes since the previous sync to the shared state and return it
+    pub async fn sync(&self) -> Result<MutexGuard<'_, SyncState>> {
+        let mut state = self.state.lock().await;
+        self.backend.sync(&mut state).await?;
+        Ok(state)
+    }
+
+    pub async fn get_cached_list(&self) -> Result<QListAction> {
+        let state = self.sync().await?;
+        Ok(QListAction::from_state(&state))
     }
 }
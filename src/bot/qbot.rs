@@ -1,72 +1,456 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 
+use futures::future::join_all;
 use rutebot::{requests::ParseMode, responses::Update};
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Instant};
 
-use crate::bot::config::QbConfig;
-use crate::bot::messages::TelegramBackend;
+use crate::bot::audit::{Actor, AuditLog};
+use crate::bot::commands::download::QDownloadAction;
+use crate::bot::commands::registry::{self, CommandKind};
+use crate::bot::config::{ConfigError, QbConfig, Role};
+use crate::bot::health;
+use crate::bot::messages::{CommandScope, TelegramBackend};
+use crate::bot::metrics;
+use crate::bot::notifier::Notifier;
 use crate::bot::qb_chat::QbChat;
+use crate::bot::watchers::{Watch, Watchers};
 
+use super::backend::QbError;
 use super::qb_client::QbClient;
 
+pub const UNREACHABLE_MESSAGE: &str = "qBittorrent is unreachable, retrying";
+pub const NOT_OWNER_MESSAGE: &str = "You can only change torrents you added";
+
 #[derive(Clone, Debug)]
 pub struct MessageWrapper {
     pub text: String,
     pub parse_mode: Option<ParseMode>,
 }
 
+struct ChatWorker {
+    tx: mpsc::UnboundedSender<Update>,
+    handler: JoinHandle<()>,
+}
+
 pub struct QbitBot {
     rbot: Arc<dyn TelegramBackend>,
-    config: QbConfig,
-    chats: Arc<RwLock<HashMap<i64, QbChat>>>,
+    /// Replaced as a whole on reload, so a command sees either old or new settings
+    config: RwLock<Arc<QbConfig>>,
+    /// Config is reloaded from it, the default location is used without it
+    config_path: Option<String>,
+    instances: Vec<Arc<QbClient>>,
+    chats: Mutex<HashMap<i64, Arc<AsyncMutex<QbChat>>>>,
+    /// Workers of chats with queued updates, a worker removes itself when its queue is empty
+    workers: Mutex<HashMap<i64, ChatWorker>>,
+    /// Set on shutdown, new updates are dropped after it
+    stopping: AtomicBool,
+    watchers: Arc<Watchers>,
+    audit: Arc<AuditLog>,
+    /// Private chats with their own command suggestions
+    chat_commands: Mutex<HashMap<i64, ChatCommands>>,
+}
+
+/// Commands suggested in a private chat by the role of its user
+struct ChatCommands {
+    username: String,
+    /// Unknown users can't run commands, so none are suggested to them
+    role: Option<Role>,
 }
 
 impl QbitBot {
     pub async fn new(conf: &QbConfig, rbot: impl TelegramBackend) -> Self {
+        let mut instances = Vec::new();
+        for instance in &conf.instances {
+            instances.push(QbClient::new(instance).await);
+        }
+        Self::with_instances(conf, rbot, instances)
+    }
+
+    /// Like [Self::new], but instances log in in background
+    pub fn start(conf: &QbConfig, rbot: impl TelegramBackend) -> Self {
+        let instances = conf.instances.iter().map(QbClient::connect_later).collect();
+        Self::with_instances(conf, rbot, instances)
+    }
+
+    fn with_instances(
+        conf: &QbConfig,
+        rbot: impl TelegramBackend,
+        instances: Vec<Arc<QbClient>>,
+    ) -> Self {
         QbitBot {
             rbot: Arc::new(rbot),
-            config: conf.to_owned(),
-            chats: Arc::new(RwLock::new(HashMap::new())),
+            config: RwLock::new(Arc::new(conf.to_owned())),
+            config_path: None,
+            instances,
+            chats: Mutex::new(HashMap::new()),
+            workers: Mutex::new(HashMap::new()),
+            stopping: AtomicBool::new(false),
+            watchers: Arc::new(Watchers::new(conf.notifications.clone())),
+            audit: Arc::new(AuditLog::new(&conf.audit_file)),
+            chat_commands: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_config_path(mut self, path: Option<String>) -> Self {
+        self.config_path = path;
+        self
+    }
+
+    pub fn config(&self) -> Arc<QbConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Read the config again and apply users, notification and display settings
+    /// and API tokens.
+    /// Returns the changes, an invalid config is rejected and the running one is kept.
+    pub fn reload(&self) -> Result<Vec<String>, ConfigError> {
+        let path = self.config_path.as_deref();
+        let new = match path {
+            // the terminal frontend runs without the token
+            _ if self.config().token.is_empty() => QbConfig::load_local(path),
+            Some(path) => QbConfig::load_path(path),
+            None => QbConfig::load(),
+        };
+        match new {
+            Ok(new) => {
+                let changes = self.apply_config(new);
+                info!("Config is reloaded: {:?}", changes);
+                Ok(changes)
+            }
+            Err(err) => {
+                error!("Config is not reloaded. {}", err);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn apply_config(&self, new: QbConfig) -> Vec<String> {
+        let mut config = self.config.write().unwrap();
+        let changes = config.changes(&new);
+        let reloaded = config.reloaded(new);
+        self.watchers.set_settings(reloaded.notifications.clone());
+        *config = Arc::new(reloaded);
+        changes
+    }
+
+    async fn send_reload_result(&self, chat_id: i64) {
+        let res = self.reload();
+        let text = match &res {
+            Ok(changes) if changes.is_empty() => {
+                String::from("Config is reloaded, nothing changed")
+            }
+            Ok(changes) => format!("Config is reloaded:\n{}", changes.join("\n")),
+            Err(err) => format!("{}\nThe running config is kept", err),
+        };
+        let msg = MessageWrapper {
+            text,
+            parse_mode: None,
+        };
+        self.rbot.send_message(chat_id, msg).await;
+        if res.is_ok() {
+            self.publish_commands().await;
         }
     }
 
+    /// Watch torrents saved by [save_state](Self::save_state) before the last shutdown
+    pub fn restore_state(&self, path: &str) {
+        let watches = match Watchers::load(path) {
+            Ok(watches) => watches,
+            Err(err) => {
+                error!("Failed to restore state: {:#}", err);
+                return;
+            }
+        };
+        for watch in watches {
+            let client = match self
+                .instances
+                .iter()
+                .find(|client| client.name() == watch.instance)
+            {
+                Some(client) => client.clone(),
+                None => {
+                    warn!(
+                        "Instance {} is gone, {} is not watched",
+                        watch.instance, watch.name
+                    );
+                    continue;
+                }
+            };
+            self.watch(client, watch);
+        }
+    }
+
+    /// Tell `watch.chat_id` when the torrent is done
+    pub fn watch(&self, client: Arc<QbClient>, watch: Watch) {
+        let label = if self.instances.len() > 1 {
+            Some(watch.instance.clone())
+        } else {
+            None
+        };
+        let tx =
+            QbChat::create_notifier_tx(self.rbot.clone(), watch.chat_id, watch.fallback_chat_id);
+        QDownloadAction::spawn_watch(client, watch, label, self.watchers.clone(), tx);
+    }
+
+    /// Forget watches of a deleted torrent, so they are not saved with the state
+    pub fn unwatch(&self, instance: &str, hash: &str) {
+        self.watchers.remove_torrent(instance, hash);
+    }
+
+    pub fn instances(&self) -> &[Arc<QbClient>] {
+        &self.instances
+    }
+
+    /// Write the audit log to `path` instead of the configured one
+    pub fn with_audit_file(mut self, path: &str) -> Self {
+        self.audit = Arc::new(AuditLog::new(path));
+        self
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Chats with queued updates, their workers are dropped when the queues are empty
+    pub fn active_chats(&self) -> usize {
+        self.workers.lock().unwrap().len()
+    }
+
+    /// Torrents watched for completion
+    pub fn active_watchers(&self) -> usize {
+        self.watchers.list().len()
+    }
+
+    pub fn save_state(&self, path: &str) {
+        match self.watchers.save(path) {
+            Ok(()) => info!("State is saved to {}", path),
+            Err(err) => error!("Failed to save state: {:#}", err),
+        }
+    }
+
+    /// Stop taking updates, then wait for queued updates and outgoing messages.
+    /// Returns false if they were not finished in `limit`.
+    pub async fn shutdown(&self, limit: Duration) -> bool {
+        let deadline = Instant::now() + limit;
+        // workers exit when their queues are empty
+        let handlers: Vec<_> = {
+            let mut workers = self.workers.lock().unwrap();
+            // set under the lock, so dispatch can't add a worker after the drain
+            self.stopping.store(true, Ordering::SeqCst);
+            workers.drain().map(|(_, worker)| worker.handler).collect()
+        };
+        if timeout(limit, join_all(handlers)).await.is_err() {
+            warn!("Updates were not processed in {:?}", limit);
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if timeout(left, self.rbot.flush()).await.is_err() {
+            warn!("Messages were not sent in {:?}", limit);
+            return false;
+        }
+        true
+    }
+
+    /// Publish command suggestions built from the command registry.
+    /// Called on every start, so suggestions follow the commands of the running version.
+    /// The default lists are of the least privileged role, private chats of other users
+    /// get their own. Run again after reload, as roles may have changed.
+    pub async fn publish_commands(&self) {
+        let viewer = registry::bot_commands(Role::Viewer);
+        self.rbot
+            .set_commands(viewer.clone(), CommandScope::AllPrivateChats)
+            .await;
+        self.rbot
+            .set_commands(viewer, CommandScope::AllGroupChats)
+            .await;
+        let config = self.config();
+        let chats: Vec<(i64, Option<Role>)> = self
+            .chat_commands
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .map(|(chat_id, chat)| {
+                chat.role = config.role(&chat.username);
+                (*chat_id, chat.role)
+            })
+            .collect();
+        for (chat_id, role) in chats {
+            self.set_chat_commands(chat_id, role).await;
+        }
+    }
+
+    /// Suggest commands of `role` in a private chat when it differs from what the chat has.
+    /// Only private chats have positive ids, in groups the suggestions are shared with users.
+    async fn suggest_commands(&self, chat_id: i64, username: &str, role: Option<Role>) {
+        if chat_id <= 0 {
+            return;
+        }
+        let chat = ChatCommands {
+            username: username.to_string(),
+            role,
+        };
+        let unchanged = match self.chat_commands.lock().unwrap().insert(chat_id, chat) {
+            Some(previous) => previous.role == role,
+            // viewers are fine with the default suggestions
+            None => role == Some(Role::Viewer),
+        };
+        if !unchanged {
+            self.set_chat_commands(chat_id, role).await;
+        }
+    }
+
+    async fn set_chat_commands(&self, chat_id: i64, role: Option<Role>) {
+        let commands = role.map(registry::bot_commands).unwrap_or_default();
+        self.rbot
+            .set_commands(commands, CommandScope::Chat { chat_id })
+            .await;
+    }
+
+    /// Queue update for processing without waiting for it.
+    /// Different chats are processed concurrently, updates of one chat are processed in order.
+    pub fn dispatch(self: &Arc<Self>, update: Update) {
+        health::update_received();
+        let chat_id = match &update.message {
+            Some(message) => message.chat.id,
+            None => return,
+        };
+        let mut workers = self.workers.lock().unwrap();
+        if self.stopping.load(Ordering::SeqCst) {
+            warn!("Shutting down, update of chat({}) is dropped", chat_id);
+            return;
+        }
+        let worker = workers
+            .entry(chat_id)
+            .or_insert_with(|| self.spawn_chat_worker(chat_id));
+        if let Err(mpsc::error::SendError(update)) = worker.tx.send(update) {
+            warn!("Worker of chat({}) has stopped. Restarting it", chat_id);
+            let new_worker = self.spawn_chat_worker(chat_id);
+            new_worker.tx.send(update).ok();
+            *worker = new_worker;
+        }
+    }
+
+    /// Must be called with the workers lock held, so the worker doesn't see an empty queue
+    /// before the first update is sent
+    fn spawn_chat_worker(self: &Arc<Self>, chat_id: i64) -> ChatWorker {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let qbot = self.clone();
+        let handler = tokio::spawn(async move {
+            loop {
+                let update = {
+                    let mut workers = qbot.workers.lock().unwrap();
+                    match rx.try_recv() {
+                        Ok(update) => update,
+                        Err(_) => {
+                            // updates are sent under the lock, so none is left behind
+                            workers.remove(&chat_id);
+                            return;
+                        }
+                    }
+                };
+                qbot.process_message(update).await;
+            }
+        });
+        ChatWorker { tx, handler }
+    }
+
+    fn get_chat(&self, chat_id: i64) -> Arc<AsyncMutex<QbChat>> {
+        self.chats
+            .lock()
+            .unwrap()
+            .entry(chat_id)
+            .or_insert_with(|| {
+                Arc::new(AsyncMutex::new(QbChat::new(
+                    chat_id,
+                    self.instances.clone(),
+                    self.watchers.clone(),
+                    self.audit.clone(),
+                )))
+            })
+            .clone()
+    }
+
     pub async fn process_message(&self, update: Update) -> Option<()> {
         let message = update.message?;
         let text = message.text?;
         let chat_id = message.chat.id;
-        let username = message.from?.username?;
-        let is_admin = self.config.admins.contains(&username);
-        if is_admin {
-            let mut chat = if let Some(chat) = self.chats.read().unwrap().get(&chat_id) {
-                chat.to_owned()
-            } else {
-                let qbclient = QbClient::new(&self.config).await;
-                QbChat::new(chat_id, qbclient)
-            };
-
-            if chat.select_goto(self.rbot.clone(), &text).await.is_err() {
-                info!("Qbit token probably expired. Trying to re-login.");
-                chat.relogin()
-                    .await
-                    .expect("Failed to re-login into Qbittorrent");
-                chat.select_goto(self.rbot.clone(), &text)
-                    .await
-                    .unwrap_or_else(|_| {
-                        error!("There is an error after re-login. Probably something has broken")
-                    });
-            };
+        let from = message.from?;
+        let username = from.username?;
+        let config = self.config();
+        if let Some(role) = config.role(&username) {
+            self.suggest_commands(chat_id, &username, Some(role)).await;
+            let chat = self.get_chat(chat_id);
+            // the lock is held for the whole command so the chat state can't be clobbered
+            let mut chat = chat.lock().await;
+            chat.set_display(&config.display);
+            chat.set_actor(Actor {
+                user_id: Some(from.id),
+                username: username.clone(),
+                chat_id: Some(chat_id),
+            });
+            chat.set_own_only(config.restricts_to_own(role));
+            chat.set_role(role);
 
-            self.chats.write().unwrap().insert(chat_id, chat);
+            let spec = chat.command_spec(&text);
+            let command = spec.map_or("text", |spec| spec.name);
+            if let Some(spec) = spec {
+                if !role.allows(spec) {
+                    metrics::count_update(command, "forbidden");
+                    let msg = MessageWrapper {
+                        text: format!("You are not allowed to use /{}", spec.name),
+                        parse_mode: None,
+                    };
+                    self.rbot.send_message(chat_id, msg).await;
+                    return Some(());
+                }
+                if spec.kind == CommandKind::Reload {
+                    metrics::count_update(command, "ok");
+                    self.send_reload_result(chat_id).await;
+                    return Some(());
+                }
+            }
+            // boxed, the command future is too large for the worker's stack in debug builds
+            let res = Box::pin(chat.select_goto(self.rbot.clone(), &text)).await;
+            if let Err(err) = res {
+                let unreachable = err
+                    .downcast_ref::<QbError>()
+                    .is_some_and(QbError::is_unreachable);
+                let reply = if unreachable {
+                    metrics::count_update(command, "unreachable");
+                    warn!("Qbittorrent is unreachable: {:#}", err);
+                    chat.reconnect();
+                    String::from(UNREACHABLE_MESSAGE)
+                } else {
+                    metrics::count_update(command, "failed");
+                    error!("Command {} failed: {:#}", text, err);
+                    format!("Command failed: {}", err)
+                };
+                let msg = MessageWrapper {
+                    text: reply,
+                    parse_mode: None,
+                };
+                self.rbot.send_message(chat_id, msg).await;
+            } else {
+                metrics::count_update(command, "ok");
+            }
 
             Some(())
         } else {
+            let command = registry::parse(&text).map_or("text", |command| command.spec.name);
+            metrics::count_update(command, "forbidden");
             let msg = MessageWrapper {
                 text: String::from("You are not allowed to chat with me"),
                 parse_mode: None,
             };
             self.rbot.send_message(chat_id, msg).await;
+            self.suggest_commands(chat_id, &username, None).await;
             info!(
                 "User {} tried to chat with qbot but he does not have access",
                 username
@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+
+pub type TorrentFields = Map<String, Value>;
+
+/// In-memory model of Qbittorrent state built from /sync/maindata responses.
+///
+/// Every response is a delta against the `rid` sent in the request, so responses
+/// have to be applied in order. `full_update` drops everything known before.
+#[derive(Debug, Clone, Default)]
+pub struct SyncState {
+    rid: i64,
+    torrents: BTreeMap<String, TorrentFields>,
+    categories: HashMap<String, Value>,
+    tags: BTreeSet<String>,
+    server_state: Map<String, Value>,
+}
+
+impl SyncState {
+    /// `rid` to send with the next /sync/maindata request
+    pub fn rid(&self) -> i64 {
+        self.rid
+    }
+
+    pub fn apply(&mut self, delta: &Value) -> Result<()> {
+        let delta = delta
+            .as_object()
+            .ok_or_else(|| anyhow!("Failed to parse Qbittorrent response"))?;
+        let rid = delta
+            .get("rid")
+            .and_then(Value::as_i64)
+            .ok_or_else(|| anyhow!("Failed to parse Qbittorrent response"))?;
+        if delta
+            .get("full_update")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+        {
+            *self = Self::default();
+        }
+        self.rid = rid;
+
+        if let Some(torrents) = delta.get("torrents").and_then(Value::as_object) {
+            for (hash, fields) in torrents {
+                let torrent = self.torrents.entry(hash.to_owned()).or_default();
+                Self::merge(torrent, fields);
+                // maindata keys torrents by hash and doesn't repeat it inside
+                torrent.insert(String::from("hash"), Value::String(hash.to_owned()));
+            }
+        }
+        for hash in Self::strings(delta.get("torrents_removed")) {
+            self.torrents.remove(&hash);
+        }
+
+        if let Some(categories) = delta.get("categories").and_then(Value::as_object) {
+            for (name, fields) in categories {
+                let category = self
+                    .categories
+                    .entry(name.to_owned())
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Some(category) = category.as_object_mut() {
+                    Self::merge(category, fields);
+                }
+            }
+        }
+        for name in Self::strings(delta.get("categories_removed")) {
+            self.categories.remove(&name);
+        }
+
+        self.tags.extend(Self::strings(delta.get("tags")));
+        for tag in Self::strings(delta.get("tags_removed")) {
+            self.tags.remove(&tag);
+        }
+
+        if let Some(server_state) = delta.get("server_state") {
+            Self::merge(&mut self.server_state, server_state);
+        }
+        Ok(())
+    }
+
+    fn merge(target: &mut Map<String, Value>, fields: &Value) {
+        if let Some(fields) = fields.as_object() {
+            for (key, value) in fields {
+                target.insert(key.to_owned(), value.to_owned());
+            }
+        }
+    }
+
+    fn strings(value: Option<&Value>) -> Vec<String> {
+        value
+            .and_then(Value::as_array)
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|item| item.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Torrents sorted by hash
+    pub fn torrents(&self) -> impl Iterator<Item = &TorrentFields> {
+        self.torrents.values()
+    }
+
+    pub fn torrent(&self, hash: &str) -> Option<&TorrentFields> {
+        self.torrents.get(hash)
+    }
+
+    pub fn hashes(&self) -> BTreeSet<String> {
+        self.torrents.keys().cloned().collect()
+    }
+
+    // the bot itself doesn't read categories and tags yet, they are kept for library users
+    #[allow(dead_code)]
+    pub fn categories(&self) -> &HashMap<String, Value> {
+        &self.categories
+    }
+
+    #[allow(dead_code)]
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    pub fn server_state(&self) -> &Map<String, Value> {
+        &self.server_state
+    }
+}
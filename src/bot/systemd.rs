@@ -0,0 +1,44 @@
+//! sd_notify protocol for `Type=notify` services. Does nothing outside of systemd.
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Send state like `READY=1` to the service manager
+pub fn notify(state: &str) {
+    let socket_path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    if socket_path.starts_with('@') {
+        warn!("Abstract NOTIFY_SOCKET is not supported");
+        return;
+    }
+    let res =
+        UnixDatagram::unbound().and_then(|socket| socket.send_to(state.as_bytes(), &socket_path));
+    if let Err(err) = res {
+        warn!("Failed to notify systemd with {}: {}", state, err);
+    }
+}
+
+/// Half of WatchdogSec, if the watchdog is enabled for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Ping the watchdog while the runtime is alive
+pub fn spawn_watchdog() {
+    if let Some(interval) = watchdog_interval() {
+        tokio::spawn(async move {
+            loop {
+                notify("WATCHDOG=1");
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+}
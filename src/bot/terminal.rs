@@ -0,0 +1,88 @@
+//! Terminal frontend for debugging menus against a local client without a bot token.
+//!
+//! Lines of stdin are messages of a single private chat and replies are printed with
+//! HTML rendered to ANSI escapes.
+use std::io::Write;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rutebot::requests::ParseMode;
+use rutebot::responses::Update;
+use serde_json::json;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::bot::html;
+use crate::bot::messages::{BotCommand, CommandScope, TelegramBackend};
+use crate::bot::qbot::{MessageWrapper, QbitBot};
+
+/// The only chat of the terminal
+pub const CHAT_ID: i64 = 1;
+
+/// [TelegramBackend] printing messages instead of sending them
+pub struct Terminal {
+    out: Mutex<Box<dyn Write + Send>>,
+    /// Without it markup is stripped, e.g. for `NO_COLOR` or a pipe
+    ansi: bool,
+}
+
+impl Terminal {
+    pub fn new(out: impl Write + Send + 'static, ansi: bool) -> Self {
+        Self {
+            out: Mutex::new(Box::new(out)),
+            ansi,
+        }
+    }
+
+    fn print(&self, text: &str) {
+        let mut out = self.out.lock().unwrap();
+        if let Err(err) = writeln!(out, "{}\n", text).and_then(|_| out.flush()) {
+            error!("Failed to print message: {}", err);
+        }
+    }
+}
+
+#[async_trait]
+impl TelegramBackend for Terminal {
+    async fn send_message(&self, chat_id: i64, message: MessageWrapper) {
+        let text = match message.parse_mode {
+            Some(ParseMode::Html) if self.ansi => html::to_ansi(&message.text),
+            Some(ParseMode::Html) => html::strip(&message.text),
+            _ => message.text,
+        };
+        if chat_id == CHAT_ID {
+            self.print(&text);
+        } else {
+            self.print(&format!("[chat {}] {}", chat_id, text));
+        }
+    }
+
+    async fn set_commands(&self, commands: Vec<BotCommand>, scope: CommandScope) {
+        debug!("Commands for {:?}: {:?}", scope, commands);
+    }
+}
+
+/// Update with a message of `username` in the terminal chat
+pub fn update(username: &str, text: &str) -> Update {
+    let message = json!({
+        "message_id": 0,
+        "date": 0,
+        "from": {"id": CHAT_ID, "is_bot": false, "first_name": username, "username": username},
+        "chat": {"id": CHAT_ID, "type": "private"},
+        "text": text
+    });
+    serde_json::from_value(json!({"update_id": 0, "message": message}))
+        .expect("Update of the terminal chat is valid")
+}
+
+/// Process lines of `input` one by one until it ends
+pub async fn run(qbot: &QbitBot, username: &str, input: impl AsyncBufRead + Unpin) -> Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        let text = line.trim();
+        if !text.is_empty() {
+            qbot.process_message(update(username, text)).await;
+        }
+    }
+    Ok(())
+}
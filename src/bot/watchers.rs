@@ -0,0 +1,89 @@
+//! Torrents watched for completion, saved on shutdown to notify about them after restart
+use std::fs;
+use std::io::ErrorKind;
+use std::sync::{Mutex, RwLock};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::bot::config::NotificationConfig;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watch {
+    pub chat_id: i64,
+    /// Chat notified instead of `chat_id` if it is unavailable, like a private chat
+    /// the user has never started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_chat_id: Option<i64>,
+    /// Name of the torrent client instance
+    pub instance: String,
+    pub hash: String,
+    pub name: String,
+}
+
+#[derive(Default)]
+pub struct Watchers {
+    active: Mutex<Vec<Watch>>,
+    settings: RwLock<NotificationConfig>,
+}
+
+impl Watchers {
+    pub fn new(settings: NotificationConfig) -> Self {
+        Self {
+            active: Mutex::default(),
+            settings: RwLock::new(settings),
+        }
+    }
+
+    /// Settings are read on every check, so running watches follow changes
+    pub fn settings(&self) -> NotificationConfig {
+        self.settings.read().unwrap().clone()
+    }
+
+    pub fn set_settings(&self, settings: NotificationConfig) {
+        *self.settings.write().unwrap() = settings;
+    }
+
+    pub fn add(&self, watch: Watch) {
+        self.active.lock().unwrap().push(watch)
+    }
+
+    pub fn remove(&self, watch: &Watch) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(pos) = active.iter().position(|item| item == watch) {
+            active.remove(pos);
+        }
+    }
+
+    /// Drop watches of a torrent that is deleted, their loops end on the next check
+    pub fn remove_torrent(&self, instance: &str, hash: &str) {
+        self.active
+            .lock()
+            .unwrap()
+            .retain(|item| item.instance != instance || item.hash != hash);
+    }
+
+    pub fn list(&self) -> Vec<Watch> {
+        self.active.lock().unwrap().clone()
+    }
+
+    /// Write to a temporary file first, so a crash doesn't leave a broken state
+    pub fn save(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.list())?;
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, json).with_context(|| format!("Failed to write {}", tmp_path))?;
+        fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace {}", path))?;
+        Ok(())
+    }
+
+    /// Saved watches, there are none before the first shutdown
+    pub fn load(path: &str) -> Result<Vec<Watch>> {
+        match fs::read_to_string(path) {
+            Ok(json) => {
+                serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", path))
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {}", path)),
+        }
+    }
+}
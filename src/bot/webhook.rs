@@ -0,0 +1,71 @@
+//! Embedded HTTP server receiving updates from Telegram in webhook mode
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use rutebot::responses::Update;
+
+use crate::bot::config::WebhookConfig;
+use crate::bot::http::{constant_time_eq, Handler, HttpServer};
+use crate::bot::qbot::QbitBot;
+
+pub const SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+struct WebhookHandler {
+    path: String,
+    secret: String,
+    qbot: Arc<QbitBot>,
+}
+
+impl WebhookHandler {
+    fn respond(status: StatusCode) -> Response<Body> {
+        let mut resp = Response::new(Body::from(status.canonical_reason().unwrap_or_default()));
+        *resp.status_mut() = status;
+        resp
+    }
+}
+
+#[async_trait]
+impl Handler for WebhookHandler {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        if req.method() != Method::POST || req.uri().path() != self.path {
+            return Self::respond(StatusCode::NOT_FOUND);
+        }
+        let header = req.headers().get(SECRET_HEADER);
+        let authorized =
+            header.is_some_and(|value| constant_time_eq(value.as_bytes(), self.secret.as_bytes()));
+        if !authorized {
+            warn!("Webhook request with wrong secret token");
+            return Self::respond(StatusCode::UNAUTHORIZED);
+        }
+        let body = match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Failed to read webhook request: {}", err);
+                return Self::respond(StatusCode::BAD_REQUEST);
+            }
+        };
+        match serde_json::from_slice::<Update>(&body) {
+            Ok(update) => {
+                self.qbot.dispatch(update);
+                Self::respond(StatusCode::OK)
+            }
+            // Telegram redelivers an update until it gets 200, which would block the queue
+            Err(err) => {
+                error!("Dropping update that failed to parse: {}", err);
+                Self::respond(StatusCode::OK)
+            }
+        }
+    }
+}
+
+/// Server passing updates from Telegram to the bot
+pub fn bind(config: &WebhookConfig, qbot: Arc<QbitBot>) -> Result<HttpServer> {
+    let handler = WebhookHandler {
+        path: config.path.clone(),
+        secret: config.secret.clone(),
+        qbot,
+    };
+    HttpServer::bind(&config.listen, handler)
+}
@@ -2,35 +2,263 @@
 extern crate log;
 extern crate pretty_env_logger;
 
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
 use futures_util::stream::StreamExt;
 use rutebot::client::Rutebot;
 
 use bot::qbot::QbitBot;
 
-use crate::bot::config::QbConfig;
+use crate::bot::api;
+use crate::bot::audit;
+use crate::bot::cli::{self, Cli, Command};
+use crate::bot::config::{ApiConfig, HealthConfig, MetricsConfig, QbConfig, Role, WebhookConfig};
+use crate::bot::health;
+use crate::bot::http::HttpServer;
+use crate::bot::messages::TelegramBot;
+use crate::bot::metrics;
+use crate::bot::outbox::Outbox;
+use crate::bot::systemd;
+use crate::bot::terminal::{self, Terminal};
+use crate::bot::webhook;
 
 mod bot;
 
-#[tokio::main]
-async fn main() {
-    let config = QbConfig::load();
-    pretty_env_logger::formatted_builder()
-        .parse_filters(&config.log_level)
-        .init();
+/// Less than TimeoutStopSec in qbitbot.service, so the state is saved before SIGKILL
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// Completes on SIGTERM from systemd or Ctrl-C
+async fn shutdown_signal() {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = sigterm.recv() => info!("Got SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Got Ctrl-C, shutting down"),
+    }
+}
+
+/// `systemctl reload` sends SIGHUP, the result is logged by [QbitBot::reload]
+fn spawn_reload_on_sighup(qbot: Arc<QbitBot>) {
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Failed to listen for SIGHUP");
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            if qbot.reload().is_ok() {
+                qbot.publish_commands().await;
+            }
+        }
+    });
+}
+
+async fn run_polling(config: &QbConfig, qbot: Arc<QbitBot>) {
     let rbot = Rutebot::new(config.token.clone());
     let mut updates_stream = Box::pin(rbot.incoming_updates(None, None));
-    let qbot = QbitBot::new(&config, rbot).await;
-    info!("QbitBot launched");
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        match updates_stream.next().await.transpose() {
+        let next = tokio::select! {
+            next = updates_stream.next() => next,
+            _ = &mut shutdown => return,
+        };
+        match next.transpose() {
             Ok(update_opt) => {
                 if let Some(update) = update_opt {
-                    qbot.process_message(update).await;
+                    qbot.dispatch(update);
                 } else {
                     error!("Failed to parse message from Telegram")
                 }
             }
-            Err(err) => error!("{:#?}", err),
+            Err(err) => {
+                // the stream fails right away while Telegram is unreachable
+                let delay = health::polling_failed();
+                error!("Failed to get updates: {:#?}. Next try in {:?}", err, delay);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => (),
+                    _ = &mut shutdown => return,
+                }
+            }
         };
     }
 }
+
+async fn run_webhook(config: &WebhookConfig, webhook: HttpServer, control: &TelegramBot) {
+    info!(
+        "Waiting for updates on {}{}",
+        webhook.local_addr(),
+        config.path
+    );
+    if let Err(err) = control.set_webhook(&config.url, &config.secret).await {
+        panic!("Failed to set webhook {}: {}", config.url, err);
+    }
+    if let Err(err) = webhook.serve(shutdown_signal()).await {
+        error!("Webhook server failed: {:#}", err);
+    }
+    if let Err(err) = control.delete_webhook().await {
+        error!("Failed to delete webhook: {}", err);
+    }
+}
+
+/// Serve the HTTP API in background until shutdown
+fn spawn_api(config: &ApiConfig, qbot: Arc<QbitBot>) {
+    let api = api::bind(config, qbot)
+        .unwrap_or_else(|err| panic!("Failed to listen on {}: {}", config.listen, err));
+    info!("HTTP API is listening on {}", api.local_addr());
+    tokio::spawn(async move {
+        if let Err(err) = api.serve(shutdown_signal()).await {
+            error!("HTTP API server failed: {:#}", err);
+        }
+    });
+}
+
+/// Serve Prometheus metrics in background until shutdown
+fn spawn_metrics(config: &MetricsConfig, qbot: Arc<QbitBot>) {
+    let server = metrics::bind(config, qbot)
+        .unwrap_or_else(|err| panic!("Failed to listen on {}: {}", config.listen, err));
+    info!("Metrics are served on {}/metrics", server.local_addr());
+    tokio::spawn(async move {
+        if let Err(err) = server.serve(shutdown_signal()).await {
+            error!("Metrics server failed: {:#}", err);
+        }
+    });
+}
+
+/// Serve `/healthz` alone in background until shutdown
+fn spawn_health(config: &HealthConfig, qbot: Arc<QbitBot>) {
+    let server = health::bind(config, qbot)
+        .unwrap_or_else(|err| panic!("Failed to listen on {}: {}", config.listen, err));
+    info!("Health is served on {}/healthz", server.local_addr());
+    tokio::spawn(async move {
+        if let Err(err) = server.serve(shutdown_signal()).await {
+            error!("Health server failed: {:#}", err);
+        }
+    });
+}
+
+/// Print result of a one-shot command, failures exit with 1
+fn finish(res: anyhow::Result<String>) {
+    match res {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            eprintln!("{:#}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, cli::USAGE);
+        process::exit(2);
+    });
+    if cli.command == Command::Help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let config = match (&cli.command, &cli.config) {
+        (Command::Repl { .. }, path) => QbConfig::load_local(path.as_deref()),
+        (_, Some(path)) => QbConfig::load_path(path),
+        (_, None) => QbConfig::load(),
+    };
+    let config = config.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&config.log_level)
+        .init();
+    match cli.command {
+        Command::Run => run(config, cli.config).await,
+        Command::CheckConfig => println!("Config is valid"),
+        Command::TestConnection { instance } => {
+            let (all_ok, report) = cli::test_connection(&config, instance.as_deref())
+                .await
+                .unwrap_or_else(|err| {
+                    eprintln!("{:#}", err);
+                    process::exit(1);
+                });
+            println!("{}", report);
+            if !all_ok {
+                process::exit(1);
+            }
+        }
+        Command::List { instance } => finish(cli::list(&config, instance.as_deref()).await),
+        Command::Add {
+            link,
+            instance,
+            user,
+        } => finish(cli::add(&config, &link, instance.as_deref(), user.as_deref()).await),
+        Command::Repl { user } => repl(config, cli.config, user).await,
+        Command::Help => unreachable!(),
+    }
+}
+
+async fn run(config: QbConfig, config_path: Option<String>) {
+    let telegram = Outbox::new(TelegramBot::new(&config.token));
+    // nothing waits for instances or Telegram before READY, systemd gives only
+    // TimeoutStartSec for the start
+    let qbot = QbitBot::start(&config, telegram);
+    let qbot = Arc::new(qbot.with_config_path(config_path));
+    let publisher = qbot.clone();
+    tokio::spawn(async move { publisher.publish_commands().await });
+    qbot.restore_state(&config.state_file);
+    let control = TelegramBot::new(&config.token);
+    spawn_reload_on_sighup(qbot.clone());
+    if let Some(api) = &config.api {
+        spawn_api(api, qbot.clone());
+    }
+    if let Some(metrics) = &config.metrics {
+        spawn_metrics(metrics, qbot.clone());
+    }
+    if let Some(health) = &config.health {
+        spawn_health(health, qbot.clone());
+    }
+    let webhook = config.webhook.as_ref().map(|webhook_config| {
+        let server = webhook::bind(webhook_config, qbot.clone())
+            .unwrap_or_else(|err| panic!("Failed to listen on {}: {}", webhook_config.listen, err));
+        (webhook_config, server)
+    });
+    info!("QbitBot launched");
+    systemd::notify("READY=1");
+    systemd::spawn_watchdog();
+    match webhook {
+        Some((webhook_config, server)) => run_webhook(webhook_config, server, &control).await,
+        None => {
+            // a webhook left by webhook mode makes getUpdates fail
+            if let Err(err) = control.delete_webhook().await {
+                warn!("Failed to delete webhook: {}", err);
+            }
+            run_polling(&config, qbot.clone()).await
+        }
+    }
+    systemd::notify("STOPPING=1");
+    if !qbot.shutdown(SHUTDOWN_TIMEOUT).await {
+        warn!("Some updates or messages are lost on shutdown");
+    }
+    qbot.save_state(&config.state_file);
+}
+
+/// Terminal chat with the bot, state and audit log of the running bot are not touched
+async fn repl(config: QbConfig, config_path: Option<String>, user: Option<String>) {
+    let user = user.unwrap_or_else(|| {
+        let admins = config
+            .users
+            .iter()
+            .filter(|(_, role)| **role == Role::Admin);
+        let first = admins.map(|(name, _)| name).min();
+        first.expect("Validated config has an admin").clone()
+    });
+    let ansi = std::env::var_os("NO_COLOR").is_none();
+    let qbot = QbitBot::new(&config, Terminal::new(std::io::stdout(), ansi)).await;
+    let qbot = qbot
+        .with_config_path(config_path)
+        .with_audit_file(&audit::repl_path(&config.audit_file));
+    println!("Chatting as {}, Ctrl-D to exit\n", user);
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    if let Err(err) = terminal::run(&qbot, &user, stdin).await {
+        eprintln!("Failed to read stdin: {:#}", err);
+        process::exit(1);
+    }
+}
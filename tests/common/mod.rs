@@ -0,0 +1,226 @@
+//! Helpers shared by integration tests
+#![allow(dead_code)]
+
+pub mod stand_in;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use rutebot::responses::Update;
+use serde_json::json;
+
+use qbitbot::bot::config::{BackendKind, InstanceConfig, QbConfig, Role};
+use qbitbot::bot::messages::{BotCommand, CommandScope, TelegramBackend};
+use qbitbot::bot::qbot::MessageWrapper;
+use qbitbot::bot::qbot::QbitBot;
+
+use stand_in::qbittorrent::{FakeQbittorrent, PASSWORD, USER};
+
+pub const MAGNET_LINK: &str = "magnet:?xt=urn:btih:60A2A94625373B5ACAE66D4C693AE5F3417690C1&tr=http%3A%2F%2Fbt3.t-ru.org%2Fann%3Fmagnet&dn=Peter%20Bruce%2C%20Andrew%20Bruce%2C%20Peter%20Gedeck%20%2F%20Питер%20Брюс%2C%20Эндрю%20Брюс%2C%20Питер%20Гедек%20-%20Practical%20Statistics%20for%20Data%20Scientists%20%2F%20Практическая%20статистика%20для";
+/// Info hash of [MAGNET_LINK] as Qbittorrent shows it
+pub const MAGNET_HASH: &str = "60a2a94625373b5acae66d4c693ae5f3417690c1";
+pub const ADMIN: &str = "Tester";
+
+/// Text message of `username` in the chat
+pub fn update(text: &str, username: &str, chat_id: i64) -> Update {
+    let message = json!(
+        {
+            "message_id": 0,
+            "date": 0,
+            "from": {"id": 0, "is_bot": false, "first_name": "Test", "username": username},
+            "chat": {"id": chat_id, "type": "private"},
+            "text": text
+        }
+    );
+    serde_json::from_value(json!({"update_id": 0, "message": message})).unwrap()
+}
+
+pub fn instance_config(name: &str, location: String) -> InstanceConfig {
+    InstanceConfig {
+        name: String::from(name),
+        backend: BackendKind::Qbittorrent,
+        location,
+        user: String::from(USER),
+        password: String::from(PASSWORD),
+    }
+}
+
+pub fn test_config(instances: Vec<InstanceConfig>) -> QbConfig {
+    QbConfig {
+        instances,
+        users: vec![(String::from(ADMIN), Role::Admin)]
+            .into_iter()
+            .collect(),
+        own_torrents_only: false,
+        log_level: String::from("info"),
+        token: String::new(),
+        webhook: None,
+        api: None,
+        metrics: None,
+        health: None,
+        state_file: String::from("state.json"),
+        // test binaries run in parallel, so they don't share the log
+        audit_file: std::env::temp_dir()
+            .join(format!("qbitbot-audit-{}.jsonl", std::process::id()))
+            .to_string_lossy()
+            .into_owned(),
+        notifications: Default::default(),
+        display: Default::default(),
+    }
+}
+
+pub struct TestCase {
+    tg: RutebotMock,
+    admin: String,
+    qbot: Arc<QbitBot>,
+    config: QbConfig,
+    fake: Option<FakeQbittorrent>,
+}
+
+impl TestCase {
+    /// Test case with a single fake Qbittorrent
+    pub async fn new() -> Self {
+        let fake = FakeQbittorrent::start();
+        let conf = test_config(vec![instance_config("default", fake.location())]);
+        Self::create(&conf, Some(fake)).await
+    }
+
+    /// Test case with custom torrent clients, e.g. for unreachable server checks
+    pub async fn with_config(conf: &QbConfig) -> Self {
+        Self::create(conf, None).await
+    }
+
+    /// Test case that reloads `conf` from `path`
+    pub async fn with_config_file(conf: &QbConfig, path: &str) -> Self {
+        let mut test_case = Self::create(conf, None).await;
+        let qbot = QbitBot::new(conf, test_case.tg.clone()).await;
+        test_case.qbot = Arc::new(qbot.with_config_path(Some(path.to_string())));
+        test_case
+    }
+
+    async fn create(conf: &QbConfig, fake: Option<FakeQbittorrent>) -> Self {
+        let admin = conf
+            .users
+            .iter()
+            .find(|(_, role)| **role == Role::Admin)
+            .map(|(name, _)| name.to_owned())
+            .unwrap();
+        let tg = RutebotMock::default();
+        Self {
+            qbot: Arc::new(QbitBot::new(conf, tg.clone()).await),
+            admin,
+            tg,
+            config: conf.to_owned(),
+            fake,
+        }
+    }
+
+    pub fn fake(&self) -> &FakeQbittorrent {
+        self.fake
+            .as_ref()
+            .expect("Test case has no fake Qbittorrent")
+    }
+
+    pub fn config(&self) -> &QbConfig {
+        &self.config
+    }
+
+    pub async fn send(&self, text: &str) {
+        self.send_from(text, &self.admin, 0).await;
+    }
+
+    pub async fn send_non_admin(&self, text: &str, username: &str) {
+        self.send_from(text, username, 0).await;
+    }
+
+    pub async fn send_from(&self, text: &str, username: &str, chat_id: i64) {
+        let update = update(text, username, chat_id);
+        self.qbot.process_message(update).await;
+    }
+
+    pub fn qbot(&self) -> &Arc<QbitBot> {
+        &self.qbot
+    }
+
+    pub fn tg(&self) -> &RutebotMock {
+        &self.tg
+    }
+
+    pub fn check(&self, wants: &str) {
+        self.tg.assert_last(wants);
+    }
+
+    pub fn last_message(&self) -> String {
+        self.tg.last_text()
+    }
+
+    /// Wait for the reply to a dispatched update
+    pub async fn wait_message(&self, wants: &str) {
+        for _ in 0..100 {
+            if self.last_message() == wants {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        self.check(wants)
+    }
+
+    pub fn get_tg_arc(&self) -> Arc<RutebotMock> {
+        Arc::new(self.tg.clone())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RutebotMock {
+    inner: Arc<RwLock<InnerRutebotMock>>,
+}
+
+#[derive(Default)]
+struct InnerRutebotMock {
+    messages: Vec<MessageWrapper>,
+    commands: HashMap<CommandScope, Vec<BotCommand>>,
+}
+
+impl RutebotMock {
+    pub fn commands(&self, scope: &CommandScope) -> Option<Vec<String>> {
+        let inner = self.inner.read().unwrap();
+        let commands = inner.commands.get(scope)?;
+        Some(commands.iter().map(|cmd| cmd.command.clone()).collect())
+    }
+
+    pub fn last_text(&self) -> String {
+        self.inner
+            .read()
+            .unwrap()
+            .messages
+            .last()
+            .map(|message| message.text.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn assert_last(&self, wants: &str) {
+        assert_eq!(
+            wants,
+            self.inner
+                .read()
+                .unwrap()
+                .messages
+                .iter()
+                .last()
+                .unwrap()
+                .text
+        )
+    }
+}
+
+#[async_trait]
+impl TelegramBackend for RutebotMock {
+    async fn send_message(&self, _: i64, message: MessageWrapper) {
+        self.inner.write().unwrap().messages.push(message);
+    }
+
+    async fn set_commands(&self, commands: Vec<BotCommand>, scope: CommandScope) {
+        self.inner.write().unwrap().commands.insert(scope, commands);
+    }
+}
@@ -0,0 +1,51 @@
+//! Local HTTP servers standing in for torrent clients
+
+pub mod qbittorrent;
+pub mod transmission;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+
+/// Serve requests with `handler` on a random local port and return its location
+pub fn serve<F, Fut>(handler: F) -> String
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_svc = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let handler = handler.clone();
+                async move { Ok::<_, Infallible>(handler(req).await) }
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let location = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    location
+}
+
+pub async fn read_body(req: Request<Body>) -> Vec<u8> {
+    hyper::body::to_bytes(req.into_body())
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+/// Parse urlencoded form of the request
+pub async fn read_form(req: Request<Body>) -> HashMap<String, String> {
+    serde_urlencoded::from_bytes(&read_body(req).await).unwrap()
+}
+
+pub fn respond(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .unwrap()
+}
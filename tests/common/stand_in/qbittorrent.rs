@@ -0,0 +1,312 @@
+//! Fake Qbittorrent WebUI API with controllable torrent state
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::header::{COOKIE, SET_COOKIE};
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+use super::{read_form, respond, serve};
+
+pub const USER: &str = "admin";
+pub const PASSWORD: &str = "adminadmin";
+/// WebAPI version of Qbittorrent 4.3
+pub const API_V4: &str = "2.8.3";
+/// WebAPI version of Qbittorrent 5.0
+pub const API_V5: &str = "2.11.0";
+
+const COMPLETION_DATE: i64 = 1600000000;
+const INFINITE_ETA: i64 = 8640000;
+
+type Torrents = BTreeMap<String, Value>;
+
+#[derive(Default)]
+struct Inner {
+    api_version: String,
+    session: Option<String>,
+    logins: usize,
+    rid: i64,
+    torrents: Torrents,
+    tags: BTreeSet<String>,
+    /// Torrents sent with every rid to answer /sync/maindata with deltas
+    snapshots: HashMap<i64, Torrents>,
+    /// Delay before every answer, like a busy client
+    delay: Duration,
+}
+
+#[derive(Clone)]
+pub struct FakeQbittorrent {
+    location: String,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl FakeQbittorrent {
+    pub fn start() -> Self {
+        Self::start_with_api(API_V4)
+    }
+
+    pub fn start_with_api(api_version: &str) -> Self {
+        let inner = Arc::new(Mutex::new(Inner {
+            api_version: api_version.to_string(),
+            ..Inner::default()
+        }));
+        let handler_inner = inner.clone();
+        let location = serve(move |req| handle(handler_inner.clone(), req));
+        Self { location, inner }
+    }
+
+    pub fn location(&self) -> String {
+        self.location.clone()
+    }
+
+    /// Forget current session, so the next request gets 403
+    pub fn expire_session(&self) {
+        self.inner.lock().unwrap().session = None;
+    }
+
+    /// Answer with another WebAPI version, as after an upgrade
+    pub fn set_api_version(&self, api_version: &str) {
+        self.inner.lock().unwrap().api_version = api_version.to_string();
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.inner.lock().unwrap().delay = delay;
+    }
+
+    pub fn logins(&self) -> usize {
+        self.inner.lock().unwrap().logins
+    }
+
+    pub fn torrent(&self, hash: &str) -> Option<Value> {
+        self.inner.lock().unwrap().torrents.get(hash).cloned()
+    }
+
+    /// Add torrent as if it was added outside of the bot
+    pub fn insert_torrent(&self, hash: &str, name: &str, tags: &str) {
+        let torrent = new_torrent(hash, name, tags);
+        self.inner
+            .lock()
+            .unwrap()
+            .torrents
+            .insert(hash.to_string(), torrent);
+    }
+
+    pub fn set_progress(&self, hash: &str, progress: f64) {
+        let mut inner = self.inner.lock().unwrap();
+        let torrent = inner.torrents.get_mut(hash).expect("No such torrent");
+        torrent["progress"] = json!(progress);
+        if progress >= 1.0 {
+            torrent["state"] = json!("uploading");
+            torrent["eta"] = json!(INFINITE_ETA);
+            torrent["completion_on"] = json!(COMPLETION_DATE);
+        }
+    }
+
+    pub fn set_ratio(&self, hash: &str, ratio: f64) {
+        let mut inner = self.inner.lock().unwrap();
+        let torrent = inner.torrents.get_mut(hash).expect("No such torrent");
+        torrent["ratio"] = json!(ratio);
+    }
+
+    pub fn complete(&self, hash: &str) {
+        self.set_progress(hash, 1.0)
+    }
+}
+
+/// Extract lowercase info hash from magnet link
+pub fn magnet_hash(link: &str) -> Option<String> {
+    let start = link.find("xt=urn:btih:")? + "xt=urn:btih:".len();
+    let hash = link[start..].split('&').next()?;
+    Some(hash.to_lowercase())
+}
+
+fn new_torrent(hash: &str, name: &str, tags: &str) -> Value {
+    json!({
+        "hash": hash,
+        "name": name,
+        "size": 1048576,
+        "progress": 0.0,
+        "eta": 60,
+        "completion_on": -1,
+        "state": "downloading",
+        "ratio": 0.0,
+        "dl_limit": 0,
+        "up_limit": 0,
+        "tags": tags,
+    })
+}
+
+fn is_authorized(inner: &Inner, req: &Request<Body>) -> bool {
+    let session = match &inner.session {
+        Some(session) => format!("SID={}", session),
+        None => return false,
+    };
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|cookies| cookies.split("; ").any(|cookie| cookie == session))
+}
+
+async fn handle(inner: Arc<Mutex<Inner>>, req: Request<Body>) -> Response<Body> {
+    let delay = inner.lock().unwrap().delay;
+    tokio::time::sleep(delay).await;
+    let path = req.uri().path().to_string();
+    if path == "/api/v2/auth/login" {
+        return login(inner, read_form(req).await);
+    }
+    if !is_authorized(&inner.lock().unwrap(), &req) {
+        return respond(StatusCode::FORBIDDEN, "Forbidden");
+    }
+    let form = read_form(req).await;
+    let mut inner = inner.lock().unwrap();
+    let v5 = inner.api_version == API_V5;
+    match path.as_str() {
+        "/api/v2/app/version" => respond(StatusCode::OK, if v5 { "v5.0.0" } else { "v4.3.9" }),
+        "/api/v2/app/webapiVersion" => respond(StatusCode::OK, inner.api_version.clone()),
+        "/api/v2/torrents/createTags" => {
+            let tags = form["tags"].split(',').map(String::from);
+            inner.tags.extend(tags);
+            respond(StatusCode::OK, "")
+        }
+        "/api/v2/torrents/add" => add(&mut inner, &form),
+        "/api/v2/torrents/info" => {
+            let torrents: Vec<&Value> = inner.torrents.values().collect();
+            respond(StatusCode::OK, json!(torrents).to_string())
+        }
+        "/api/v2/torrents/properties" => properties(&inner, &form),
+        "/api/v2/torrents/pause" if !v5 => set_state(&mut inner, &form, "pausedDL"),
+        "/api/v2/torrents/resume" if !v5 => set_state(&mut inner, &form, "downloading"),
+        "/api/v2/torrents/stop" if v5 => set_state(&mut inner, &form, "stoppedDL"),
+        "/api/v2/torrents/start" if v5 => set_state(&mut inner, &form, "downloading"),
+        "/api/v2/torrents/setDownloadLimit" => set_limit(&mut inner, &form, "dl_limit"),
+        "/api/v2/torrents/setUploadLimit" => set_limit(&mut inner, &form, "up_limit"),
+        "/api/v2/torrents/delete" => {
+            for hash in form["hashes"].split('|') {
+                inner.torrents.remove(hash);
+            }
+            respond(StatusCode::OK, "")
+        }
+        "/api/v2/sync/maindata" => maindata(&mut inner, &form),
+        _ => respond(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+fn login(inner: Arc<Mutex<Inner>>, form: HashMap<String, String>) -> Response<Body> {
+    let mut inner = inner.lock().unwrap();
+    if form.get("username").map(String::as_str) != Some(USER)
+        || form.get("password").map(String::as_str) != Some(PASSWORD)
+    {
+        return respond(StatusCode::OK, "Fails.");
+    }
+    inner.logins += 1;
+    let session = format!("session{}", inner.logins);
+    inner.session = Some(session.clone());
+    Response::builder()
+        .header(SET_COOKIE, format!("SID={}; HttpOnly; path=/", session))
+        .body(Body::from("Ok."))
+        .unwrap()
+}
+
+fn add(inner: &mut Inner, form: &HashMap<String, String>) -> Response<Body> {
+    let hash = match form.get("urls").and_then(|urls| magnet_hash(urls)) {
+        Some(hash) if !inner.torrents.contains_key(&hash) => hash,
+        _ => return respond(StatusCode::OK, "Fails."),
+    };
+    let name = format!("torrent {}", &hash[..4]);
+    let tags = form.get("tags").cloned().unwrap_or_default();
+    inner
+        .torrents
+        .insert(hash.clone(), new_torrent(&hash, &name, &tags));
+    respond(StatusCode::OK, "Ok.")
+}
+
+fn properties(inner: &Inner, form: &HashMap<String, String>) -> Response<Body> {
+    match inner.torrents.get(&form["hash"]) {
+        Some(torrent) => {
+            let props = json!({
+                "save_path": "/downloads/",
+                "total_size": torrent["size"],
+                "completion_date": torrent["completion_on"],
+                "addition_date": COMPLETION_DATE,
+            });
+            respond(StatusCode::OK, props.to_string())
+        }
+        None => respond(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+fn set_state(inner: &mut Inner, form: &HashMap<String, String>, state: &str) -> Response<Body> {
+    for hash in form["hashes"].split('|') {
+        if let Some(torrent) = inner.torrents.get_mut(hash) {
+            torrent["state"] = json!(state);
+        }
+    }
+    respond(StatusCode::OK, "")
+}
+
+fn set_limit(inner: &mut Inner, form: &HashMap<String, String>, field: &str) -> Response<Body> {
+    let limit: i64 = match form["limit"].parse() {
+        Ok(limit) => limit,
+        Err(_) => return respond(StatusCode::BAD_REQUEST, "Bad Request"),
+    };
+    for hash in form["hashes"].split('|') {
+        if let Some(torrent) = inner.torrents.get_mut(hash) {
+            torrent[field] = json!(limit);
+        }
+    }
+    respond(StatusCode::OK, "")
+}
+
+/// Changed fields of torrents and removed hashes since `old`
+fn delta(old: &Torrents, new: &Torrents) -> (Map<String, Value>, Vec<String>) {
+    let mut changed = Map::new();
+    for (hash, torrent) in new {
+        let fields = match (old.get(hash), torrent.as_object()) {
+            (Some(Value::Object(old_fields)), Some(fields)) => fields
+                .iter()
+                .filter(|(key, value)| old_fields.get(*key) != Some(*value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            _ => torrent.as_object().cloned().unwrap_or_default(),
+        };
+        if !fields.is_empty() {
+            changed.insert(hash.clone(), Value::Object(fields));
+        }
+    }
+    let removed = old
+        .keys()
+        .filter(|hash| !new.contains_key(*hash))
+        .cloned()
+        .collect();
+    (changed, removed)
+}
+
+fn maindata(inner: &mut Inner, form: &HashMap<String, String>) -> Response<Body> {
+    let old = form
+        .get("rid")
+        .and_then(|rid| rid.parse::<i64>().ok())
+        .and_then(|rid| inner.snapshots.get(&rid));
+    let mut body = match old {
+        Some(old) => {
+            let (torrents, removed) = delta(old, &inner.torrents);
+            json!({"torrents": torrents, "torrents_removed": removed})
+        }
+        None => json!({
+            "full_update": true,
+            "torrents": inner.torrents,
+            "tags": inner.tags,
+            "server_state": {
+                "connection_status": "connected",
+                "dl_info_speed": 0,
+                "up_info_speed": 0,
+            },
+        }),
+    };
+    inner.rid += 1;
+    body["rid"] = json!(inner.rid);
+    let snapshot = inner.torrents.clone();
+    inner.snapshots.insert(inner.rid, snapshot);
+    respond(StatusCode::OK, body.to_string())
+}
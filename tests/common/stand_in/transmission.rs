@@ -0,0 +1,133 @@
+//! Fake Transmission RPC
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{json, Value};
+
+use super::qbittorrent::magnet_hash;
+use super::{read_body, respond, serve};
+
+const SESSION_HEADER: &str = "X-Transmission-Session-Id";
+const SESSION_ID: &str = "fake-session";
+/// base64 of "admin:adminadmin"
+const BASIC_AUTH: &str = "Basic YWRtaW46YWRtaW5hZG1pbg==";
+
+#[derive(Default)]
+struct Inner {
+    handshakes: usize,
+    torrents: BTreeMap<String, Value>,
+}
+
+#[derive(Clone)]
+pub struct FakeTransmission {
+    location: String,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl FakeTransmission {
+    pub fn start() -> Self {
+        let inner = Arc::new(Mutex::new(Inner::default()));
+        let handler_inner = inner.clone();
+        let location = serve(move |req| handle(handler_inner.clone(), req));
+        Self { location, inner }
+    }
+
+    pub fn location(&self) -> String {
+        self.location.clone()
+    }
+
+    /// How many times the client was sent to get a session id
+    pub fn handshakes(&self) -> usize {
+        self.inner.lock().unwrap().handshakes
+    }
+}
+
+async fn handle(inner: Arc<Mutex<Inner>>, req: Request<Body>) -> Response<Body> {
+    if req.uri().path() != "/transmission/rpc" {
+        return respond(StatusCode::NOT_FOUND, "");
+    }
+    if req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        != Some(BASIC_AUTH)
+    {
+        return respond(StatusCode::UNAUTHORIZED, "");
+    }
+    if req
+        .headers()
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        != Some(SESSION_ID)
+    {
+        inner.lock().unwrap().handshakes += 1;
+        return Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header(SESSION_HEADER, SESSION_ID)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let request: Value = serde_json::from_slice(&read_body(req).await).unwrap();
+    let args = &request["arguments"];
+    let mut inner = inner.lock().unwrap();
+    let result = match request["method"].as_str().unwrap() {
+        "session-get" => Ok(json!({"version": "3.00", "rpc-version": 15})),
+        "torrent-get" => {
+            let torrents: Vec<Value> = inner
+                .torrents
+                .values()
+                .filter(|torrent| match args["ids"].as_array() {
+                    Some(ids) => ids.contains(&torrent["hashString"]),
+                    None => true,
+                })
+                .cloned()
+                .collect();
+            Ok(json!({ "torrents": torrents }))
+        }
+        "torrent-add" => match magnet_hash(args["filename"].as_str().unwrap()) {
+            Some(hash) if inner.torrents.contains_key(&hash) => {
+                Ok(json!({"torrent-duplicate": {"hashString": hash}}))
+            }
+            Some(hash) => {
+                let torrent = json!({
+                    "hashString": hash,
+                    "name": format!("torrent {}", &hash[..4]),
+                    "totalSize": 1048576,
+                    "percentDone": 0.0,
+                    "eta": -1,
+                    "doneDate": 0,
+                    "status": 4,
+                    "downloadDir": "/downloads",
+                    "addedDate": 1600000000,
+                    "uploadRatio": 0.0,
+                    "rateDownload": 0,
+                    "rateUpload": 0,
+                    "peersConnected": 0,
+                    "labels": args.get("labels").cloned().unwrap_or_else(|| json!([])),
+                });
+                inner.torrents.insert(hash.clone(), torrent);
+                Ok(json!({"torrent-added": {"hashString": hash}}))
+            }
+            None => Err("invalid or corrupt torrent file"),
+        },
+        method @ ("torrent-stop" | "torrent-start" | "torrent-remove") => {
+            for id in args["ids"].as_array().unwrap() {
+                let hash = id.as_str().unwrap();
+                if method == "torrent-remove" {
+                    inner.torrents.remove(hash);
+                } else if let Some(torrent) = inner.torrents.get_mut(hash) {
+                    torrent["status"] = json!(if method == "torrent-stop" { 0 } else { 4 });
+                }
+            }
+            Ok(json!({}))
+        }
+        _ => Err("method name not recognized"),
+    };
+    let body = match result {
+        Ok(arguments) => json!({"result": "success", "arguments": arguments}),
+        Err(error) => json!({"result": error, "arguments": {}}),
+    };
+    respond(StatusCode::OK, body.to_string())
+}
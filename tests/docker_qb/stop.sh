@@ -1,6 +0,0 @@
-#!/bin/bash
-
-docker-compose stop
-docker-compose rm -f
-rm -rf config
-rm -rf downloads
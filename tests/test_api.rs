@@ -0,0 +1,257 @@
+use std::time::Duration;
+
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use common::stand_in::qbittorrent::{FakeQbittorrent, PASSWORD};
+use common::{instance_config, test_config, TestCase, ADMIN, MAGNET_HASH, MAGNET_LINK};
+use qbitbot::bot::api;
+use qbitbot::bot::config::{ApiConfig, ApiToken, QbConfig, Role};
+
+mod common;
+
+const ADMIN_TOKEN: &str = "admin-token";
+const VIEWER_TOKEN: &str = "viewer-token";
+
+fn api_config() -> ApiConfig {
+    ApiConfig {
+        listen: ([127, 0, 0, 1], 0).into(),
+        tokens: vec![
+            ApiToken {
+                name: String::from("dashboard"),
+                token: String::from(ADMIN_TOKEN),
+                user: String::from(ADMIN),
+                chat_id: Some(1),
+            },
+            ApiToken {
+                name: String::from("status"),
+                token: String::from(VIEWER_TOKEN),
+                user: String::from("viewer"),
+                chat_id: None,
+            },
+        ],
+    }
+}
+
+#[tokio::test]
+async fn test_api() {
+    let fake = FakeQbittorrent::start();
+    let mut conf = test_config(vec![instance_config("default", fake.location())]);
+    conf.users.insert(String::from("viewer"), Role::Viewer);
+    conf.api = Some(api_config());
+    let test_case = TestCase::with_config(&conf).await;
+    let api = api::bind(&api_config(), test_case.qbot().clone()).unwrap();
+    let location = format!("http://{}", api.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(api.serve(async {
+        stop_rx.await.ok();
+    }));
+
+    let client = reqwest::Client::new();
+    let request = |method: Method, path: &str, token: &str, body: Option<Value>| {
+        let mut req = client
+            .request(method, format!("{}{}", location, path))
+            .bearer_auth(token);
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+        async move {
+            let resp = req.send().await.unwrap();
+            let status = resp.status();
+            (status, resp.json::<Value>().await.unwrap())
+        }
+    };
+    let torrent = format!("/api/torrents/{}", MAGNET_HASH);
+
+    let (status, _) = request(Method::GET, "/api/torrents", "wrong", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = request(Method::GET, "/api/torrents", VIEWER_TOKEN, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"instance": "default", "torrents": []}));
+
+    let add = json!({"link": MAGNET_LINK, "subscribe": true});
+    let (status, body) = request(
+        Method::POST,
+        "/api/torrents",
+        VIEWER_TOKEN,
+        Some(add.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "You are not allowed to use /download");
+    let (status, body) = request(Method::POST, "/api/torrents", ADMIN_TOKEN, Some(add)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"status": "OK", "hash": MAGNET_HASH}));
+
+    let (_, body) = request(Method::GET, "/api/torrents", VIEWER_TOKEN, None).await;
+    assert_eq!(body["torrents"][0]["id"], 0);
+    assert_eq!(body["torrents"][0]["hash"], MAGNET_HASH);
+    assert_eq!(body["torrents"][0]["owner"], ADMIN);
+
+    let path = format!("{}/pause", torrent);
+    let (status, _) = request(Method::POST, &path, VIEWER_TOKEN, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = request(Method::POST, &path, ADMIN_TOKEN, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fake.torrent(MAGNET_HASH).unwrap()["state"], "pausedDL");
+    let path = format!("{}/resume", torrent);
+    let (status, _) = request(Method::POST, &path, ADMIN_TOKEN, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fake.torrent(MAGNET_HASH).unwrap()["state"], "downloading");
+
+    // the viewer token has no chat for notifications
+    let path = format!("{}/subscribe", torrent);
+    let (status, body) = request(Method::POST, &path, VIEWER_TOKEN, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"],
+        "Token status has no chat_id for notifications"
+    );
+    fake.complete(MAGNET_HASH);
+    test_case.wait_message("torrent 60a2 is done").await;
+
+    let (status, _) = request(Method::DELETE, &torrent, VIEWER_TOKEN, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = request(Method::DELETE, &torrent, ADMIN_TOKEN, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(fake.torrent(MAGNET_HASH).is_none());
+    let (status, body) = request(Method::DELETE, &torrent, ADMIN_TOKEN, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        body["error"],
+        format!("There is no torrent {}", MAGNET_HASH)
+    );
+
+    let (status, _) = request(Method::GET, "/api/other", ADMIN_TOKEN, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let huge = json!({ "link": "a".repeat(100 * 1024) });
+    let (status, body) = request(Method::POST, "/api/torrents", ADMIN_TOKEN, Some(huge)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["error"], "Request body is larger than 65536 bytes");
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_api_reload() {
+    let fake = FakeQbittorrent::start();
+    let path = std::env::temp_dir().join(format!("qbitbot-api-{}.toml", std::process::id()));
+    let path = path.to_str().unwrap();
+    let write = |name: &str, token: &str| {
+        let text = format!(
+            "token = \"123:abc\"\n\
+             [[instances]]\n\
+             name = \"apitest\"\n\
+             location = \"{}\"\n\
+             user = \"admin\"\n\
+             password = \"{}\"\n\
+             [users]\n\
+             {} = \"admin\"\n\
+             [api]\n\
+             listen = \"127.0.0.1:0\"\n\
+             [api.tokens.{}]\n\
+             token = \"{}\"\n\
+             user = \"{}\"\n",
+            fake.location(),
+            PASSWORD,
+            ADMIN,
+            name,
+            token,
+            ADMIN
+        );
+        std::fs::write(path, text).unwrap();
+    };
+    write("dashboard", ADMIN_TOKEN);
+    let conf = QbConfig::load_path(path).unwrap();
+    let test_case = TestCase::with_config_file(&conf, path).await;
+    let api = api::bind(conf.api.as_ref().unwrap(), test_case.qbot().clone()).unwrap();
+    let location = format!("http://{}/api/torrents", api.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(api.serve(async {
+        stop_rx.await.ok();
+    }));
+
+    let client = reqwest::Client::new();
+    let status = |token: &str| {
+        let req = client.get(&location).bearer_auth(token);
+        async move { req.send().await.unwrap().status() }
+    };
+    assert_eq!(status(ADMIN_TOKEN).await, StatusCode::OK);
+
+    // the old token is revoked without restart
+    write("cron", "cron-token");
+    test_case.send("/reload").await;
+    test_case.check("Config is reloaded:\nAPI tokens: cron");
+    assert_eq!(status(ADMIN_TOKEN).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status("cron-token").await, StatusCode::OK);
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_api_delete_watched() {
+    let fake = FakeQbittorrent::start();
+    let mut conf = test_config(vec![instance_config("default", fake.location())]);
+    conf.api = Some(api_config());
+    let test_case = TestCase::with_config(&conf).await;
+    let qbot = test_case.qbot().clone();
+    let api = api::bind(&api_config(), qbot.clone()).unwrap();
+    let location = format!("http://{}/api/torrents", api.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(api.serve(async {
+        stop_rx.await.ok();
+    }));
+    let client = reqwest::Client::new();
+
+    let add = json!({"link": MAGNET_LINK, "subscribe": true});
+    let resp = client
+        .post(&location)
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&add)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(qbot.active_watchers(), 1);
+    let resp = client
+        .delete(format!("{}/{}", location, MAGNET_HASH))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(qbot.active_watchers(), 0);
+
+    // deleted outside of the bot, the watch ends when the hash is gone from the client
+    let hash = "b".repeat(40);
+    fake.insert_torrent(&hash, "other", "");
+    let resp = client
+        .post(format!("{}/{}/subscribe", location, hash))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(qbot.active_watchers(), 1);
+    qbot.instances()[0]
+        .backend()
+        .delete(&hash, false)
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while qbot.active_watchers() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("the watch of a deleted torrent is not removed");
+    assert!(!test_case.last_message().contains("is done"));
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
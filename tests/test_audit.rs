@@ -0,0 +1,69 @@
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, TestCase, ADMIN, MAGNET_HASH, MAGNET_LINK};
+use qbitbot::bot::audit::{self, AuditLog};
+use qbitbot::bot::config::Role;
+
+mod common;
+
+#[tokio::test]
+async fn test_audit() {
+    let fake = FakeQbittorrent::start();
+    let mut conf = test_config(vec![instance_config("default", fake.location())]);
+    conf.users.insert(String::from("carol"), Role::User);
+    let test_case = TestCase::with_config(&conf).await;
+    let audit = AuditLog::new(&conf.audit_file);
+
+    test_case.send("/audit").await;
+    test_case.check("Audit log is empty");
+    test_case.send_from("/audit", "carol", 1).await;
+    test_case.check("You are not allowed to use /audit");
+
+    fake.insert_torrent("aaaa", "first", "");
+    test_case.send("/pause 0").await;
+    test_case.send_from("/limit 0 dl=2M", "carol", 1).await;
+    test_case.send("/download").await;
+    test_case.send(MAGNET_LINK).await;
+
+    let entries = audit.recent(10).unwrap();
+    assert_eq!(entries.len(), 3);
+    let pause = &entries[0];
+    assert_eq!(pause.username, ADMIN);
+    assert_eq!(pause.user_id, Some(0));
+    assert_eq!(pause.chat_id, Some(0));
+    assert_eq!(pause.command, "pause");
+    assert_eq!(pause.instance, "default");
+    assert_eq!(pause.hash.as_deref(), Some("aaaa"));
+    assert_eq!(pause.name.as_deref(), Some("first"));
+    assert_eq!(pause.outcome, "OK");
+    let limit = &entries[1];
+    assert_eq!(limit.username, "carol");
+    assert_eq!(limit.chat_id, Some(1));
+    assert_eq!(limit.params["dl"], "2M");
+    let download = &entries[2];
+    assert_eq!(download.command, "download");
+    assert_eq!(download.hash.as_deref(), Some(MAGNET_HASH));
+    assert_eq!(download.params["link"], MAGNET_LINK);
+    assert_eq!(download.outcome, "OK");
+
+    test_case.send("/audit 2").await;
+    let text = test_case.last_message();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", text);
+    assert!(
+        lines[0].ends_with(" carol /limit first dl=2M: OK"),
+        "{}",
+        text
+    );
+    assert!(
+        lines[1].contains(&format!(" {} /download ", ADMIN)),
+        "{}",
+        text
+    );
+
+    std::fs::remove_file(&conf.audit_file).unwrap();
+    // terminal sessions don't mix into the log of the bot
+    assert_eq!(
+        audit::repl_path("/var/lib/audit.jsonl"),
+        "/var/lib/audit.repl.jsonl"
+    );
+}
@@ -0,0 +1,124 @@
+use qbitbot::bot::backend::{QbError, QbittorrentBackend, TorrentBackend, TransmissionBackend};
+use qbitbot::bot::config::{BackendKind, InstanceConfig};
+use qbitbot::bot::sync::SyncState;
+use qbitbot::bot::tag_owner;
+
+use common::stand_in::qbittorrent::{FakeQbittorrent, API_V5, PASSWORD, USER};
+use common::stand_in::transmission::FakeTransmission;
+
+mod common;
+
+const HASH: &str = "60a2a94625373b5acae66d4c693ae5f3417690c1";
+const MAGNET: &str = "magnet:?xt=urn:btih:60A2A94625373B5ACAE66D4C693AE5F3417690C1&dn=test";
+
+fn config(backend: BackendKind, location: String, password: &str) -> InstanceConfig {
+    InstanceConfig {
+        name: String::from("test"),
+        backend,
+        location,
+        user: String::from(USER),
+        password: String::from(password),
+    }
+}
+
+async fn check_backend(backend: &dyn TorrentBackend, paused_state: &str) {
+    backend.connect().await.unwrap();
+    backend.add(MAGNET, Some("alice")).await.unwrap();
+    assert!(backend.add(MAGNET, None).await.is_err());
+
+    let mut state = SyncState::default();
+    backend.sync(&mut state).await.unwrap();
+    let torrent = state.torrent(HASH).unwrap();
+    assert_eq!(torrent["name"], "torrent 60a2");
+    assert_eq!(torrent["progress"], 0.0);
+    assert_eq!(torrent["state"], "downloading");
+    assert_eq!(tag_owner(torrent["tags"].as_str().unwrap()), Some("alice"));
+    assert_eq!(state.server_state()["dl_info_speed"], 0);
+
+    backend.pause(HASH).await.unwrap();
+    backend.sync(&mut state).await.unwrap();
+    let state_name = state.torrent(HASH).unwrap()["state"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(state_name, paused_state);
+    assert!(backend.is_paused(&state_name));
+    backend.resume(HASH).await.unwrap();
+    backend.sync(&mut state).await.unwrap();
+    assert_eq!(state.torrent(HASH).unwrap()["state"], "downloading");
+
+    assert_eq!(backend.list().await.unwrap().len(), 1);
+    backend.delete(HASH, true).await.unwrap();
+    backend.sync(&mut state).await.unwrap();
+    assert!(state.torrent(HASH).is_none());
+}
+
+#[tokio::test]
+async fn test_qbittorrent_backend() {
+    let fake = FakeQbittorrent::start();
+    let backend =
+        QbittorrentBackend::new(&config(BackendKind::Qbittorrent, fake.location(), PASSWORD));
+    check_backend(&backend, "pausedDL").await;
+    assert_eq!(fake.logins(), 1);
+    assert_eq!(backend.api_version(), (2, 8));
+}
+
+#[tokio::test]
+async fn test_qbittorrent5_backend() {
+    let fake = FakeQbittorrent::start_with_api(API_V5);
+    let backend =
+        QbittorrentBackend::new(&config(BackendKind::Qbittorrent, fake.location(), PASSWORD));
+    check_backend(&backend, "stoppedDL").await;
+    assert_eq!(backend.api_version(), (2, 11));
+}
+
+#[tokio::test]
+async fn test_qbittorrent_relogin() {
+    let fake = FakeQbittorrent::start();
+    let backend =
+        QbittorrentBackend::new(&config(BackendKind::Qbittorrent, fake.location(), PASSWORD));
+    backend.connect().await.unwrap();
+    fake.expire_session();
+    fake.set_api_version(API_V5);
+    backend.add(MAGNET, None).await.unwrap();
+    assert_eq!(fake.logins(), 2);
+    // the request is replayed only once
+    assert!(fake.torrent(HASH).is_some());
+    // the client was upgraded while the session was expired
+    assert_eq!(backend.api_version(), (2, 11));
+}
+
+#[tokio::test]
+async fn test_qbittorrent_wrong_password() {
+    let fake = FakeQbittorrent::start();
+    let backend =
+        QbittorrentBackend::new(&config(BackendKind::Qbittorrent, fake.location(), "wrong"));
+    let err = backend.connect().await.unwrap_err();
+    assert!(err.downcast_ref::<QbError>().unwrap().is_unreachable());
+}
+
+#[tokio::test]
+async fn test_transmission_backend() {
+    let fake = FakeTransmission::start();
+    let backend = TransmissionBackend::new(&config(
+        BackendKind::Transmission,
+        fake.location(),
+        PASSWORD,
+    ));
+    check_backend(&backend, "pausedDL").await;
+    assert_eq!(fake.handshakes(), 1);
+
+    backend.add(MAGNET, None).await.unwrap();
+    let props = backend.properties(HASH).await.unwrap();
+    assert_eq!(props["save_path"], "/downloads");
+    assert_eq!(props["completion_date"], -1);
+}
+
+#[tokio::test]
+async fn test_transmission_wrong_password() {
+    let fake = FakeTransmission::start();
+    let backend =
+        TransmissionBackend::new(&config(BackendKind::Transmission, fake.location(), "wrong"));
+    let err = backend.connect().await.unwrap_err();
+    assert!(err.downcast_ref::<QbError>().unwrap().is_unreachable());
+}
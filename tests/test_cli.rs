@@ -0,0 +1,110 @@
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, MAGNET_HASH, MAGNET_LINK};
+use qbitbot::bot::audit::AuditLog;
+use qbitbot::bot::cli::{self, Cli, Command};
+
+mod common;
+
+fn parse(args: &str) -> anyhow::Result<Cli> {
+    Cli::parse(args.split_whitespace().map(String::from))
+}
+
+#[test]
+fn test_parse() {
+    let cli = parse("").unwrap();
+    assert_eq!(cli.config, None);
+    assert_eq!(cli.command, Command::Run);
+
+    let cli = parse("--config=/etc/qbitbot.toml check-config").unwrap();
+    assert_eq!(cli.config.as_deref(), Some("/etc/qbitbot.toml"));
+    assert_eq!(cli.command, Command::CheckConfig);
+    assert_eq!(
+        parse("--check-config").unwrap().command,
+        Command::CheckConfig
+    );
+
+    let cli =
+        parse("add magnet:?xt=urn:btih:abc --instance nas --user bob --config q.toml").unwrap();
+    assert_eq!(cli.config.as_deref(), Some("q.toml"));
+    assert_eq!(
+        cli.command,
+        Command::Add {
+            link: String::from("magnet:?xt=urn:btih:abc"),
+            instance: Some(String::from("nas")),
+            user: Some(String::from("bob")),
+        }
+    );
+
+    let cli = parse("repl --user alice").unwrap();
+    assert_eq!(
+        cli.command,
+        Command::Repl {
+            user: Some(String::from("alice"))
+        }
+    );
+
+    let err = |args| parse(args).unwrap_err().to_string();
+    assert_eq!(err("add"), "add needs a link");
+    assert_eq!(err("list --instance"), "--instance needs a value");
+    assert_eq!(err("list extra"), "Unexpected argument extra");
+    assert_eq!(err("remove"), "Unknown command remove");
+    assert_eq!(err("--instance nas"), "run doesn't take --instance");
+    assert_eq!(err("repl --instance nas"), "repl doesn't take --instance");
+    assert_eq!(err("list --user alice"), "list doesn't take --user");
+    assert_eq!(err("--verbose"), "Unknown option --verbose");
+}
+
+#[tokio::test]
+async fn test_connection() {
+    let fake = FakeQbittorrent::start();
+    let mut conf = test_config(vec![instance_config("default", fake.location())]);
+    let (all_ok, report) = cli::test_connection(&conf, None).await.unwrap();
+    assert!(all_ok);
+    assert_eq!(report, "default: OK, qBittorrent v4.3.9, WebAPI 2.8");
+
+    conf.instances
+        .push(instance_config("down", String::from("http://localhost:1")));
+    let (all_ok, report) = cli::test_connection(&conf, None).await.unwrap();
+    assert!(!all_ok);
+    assert!(report.contains("\ndown: FAILED, "), "{}", report);
+    // only the named instance is checked
+    let (all_ok, report) = cli::test_connection(&conf, Some("default")).await.unwrap();
+    assert!(all_ok);
+    assert!(!report.contains("down"), "{}", report);
+    let err = cli::test_connection(&conf, Some("nas")).await.unwrap_err();
+    assert_eq!(err.to_string(), "There is no instance nas");
+}
+
+#[tokio::test]
+async fn test_list_and_add() {
+    let fake = FakeQbittorrent::start();
+    let conf = test_config(vec![instance_config("default", fake.location())]);
+    assert_eq!(
+        cli::list(&conf, None).await.unwrap(),
+        "There are no torrents"
+    );
+    assert_eq!(
+        cli::add(&conf, MAGNET_LINK, None, Some("alice"))
+            .await
+            .unwrap(),
+        "OK"
+    );
+    assert_eq!(
+        fake.torrent(MAGNET_HASH).unwrap()["tags"],
+        "qbitbot,qbitbot:alice"
+    );
+    let entries = AuditLog::new(&conf.audit_file).recent(10).unwrap();
+    std::fs::remove_file(&conf.audit_file).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].username, "alice");
+    assert_eq!(entries[0].command, "download");
+    assert_eq!(entries[0].hash.as_deref(), Some(MAGNET_HASH));
+    assert_eq!(entries[0].params["source"], "cli");
+
+    let list = cli::list(&conf, Some("default")).await.unwrap();
+    assert!(list.starts_with("/torrent0 | "), "{}", list);
+    assert!(!list.contains("<code>"));
+
+    let err = cli::list(&conf, Some("nas")).await.unwrap_err();
+    assert_eq!(err.to_string(), "There is no instance nas");
+}
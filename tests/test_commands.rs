@@ -0,0 +1,129 @@
+use common::{TestCase, ADMIN};
+use qbitbot::bot::commands::registry::{parse, CommandKind, ParseError};
+use qbitbot::bot::messages::CommandScope;
+
+mod common;
+
+#[test]
+fn test_parse() {
+    let command = parse("/limit 3 dl=2M").unwrap();
+    assert_eq!(command.kind(), CommandKind::Limit);
+    assert_eq!(command.id(), Some(3));
+    assert_eq!(command.speed("dl"), Some(2 * 1024 * 1024));
+    assert_eq!(command.speed("up"), None);
+
+    let command = parse("/list seeding sort=ratio").unwrap();
+    assert_eq!(command.get("filter"), Some("seeding"));
+    assert_eq!(command.get("sort"), Some("ratio"));
+
+    assert_eq!(parse("/torrent3").unwrap().id(), Some(3));
+    assert_eq!(parse("/stop@qbitbot 1").unwrap().kind(), CommandKind::Pause);
+    assert!(matches!(parse("magnet:?xt"), Err(ParseError::NotCommand)));
+    assert!(matches!(parse("/qwer"), Err(ParseError::Unknown(_))));
+}
+
+#[test]
+fn test_usage_errors() {
+    let check = |text: &str, wants: &str| {
+        assert_eq!(parse(text).unwrap_err().to_string(), wants);
+    };
+    check("/torrent", "Missing argument id\nUsage: /torrent <id>");
+    check(
+        "/pause first",
+        "Wrong value \"first\", expected <id>\nUsage: /pause [id]",
+    );
+    check(
+        "/limit 1 down=2M",
+        "Unknown option down\nUsage: /limit [id] [dl=<speed>] [up=<speed>]",
+    );
+    check(
+        "/list 1 2",
+        "Wrong value \"1\", expected all|downloading|seeding|completed|paused\n\
+         Usage: /list [all|downloading|seeding|completed|paused] [sort=name|size|progress|ratio]",
+    );
+}
+
+#[tokio::test]
+async fn test_commands_from_any_menu() {
+    let test_case = TestCase::new().await;
+    let fake = test_case.fake();
+    fake.insert_torrent("aaaa", "first", "");
+    fake.insert_torrent("bbbb", "second", "");
+    fake.complete("aaaa");
+    fake.set_ratio("aaaa", 0.5);
+    fake.insert_torrent("cccc", "third", "");
+    fake.complete("cccc");
+    fake.set_ratio("cccc", 2.0);
+
+    test_case.send("/pause 1").await;
+    test_case.check("OK");
+    assert_eq!(fake.torrent("bbbb").unwrap()["state"], "pausedDL");
+
+    test_case.send("/limit 1 dl=2M up=512K").await;
+    test_case.check("OK");
+    let torrent = fake.torrent("bbbb").unwrap();
+    assert_eq!(torrent["dl_limit"], 2 * 1024 * 1024);
+    assert_eq!(torrent["up_limit"], 512 * 1024);
+
+    test_case.send("/limit").await;
+    test_case.check("Open torrent page or pass id\nUsage: /limit [id] [dl=<speed>] [up=<speed>]");
+
+    test_case.send("/list seeding sort=ratio").await;
+    let list = test_case.last_message();
+    let third = list.find("/torrent2").unwrap();
+    let first = list.find("/torrent0").unwrap();
+    assert!(third < first);
+    assert!(!list.contains("/torrent1"));
+
+    test_case.send("/list paused").await;
+    let list = test_case.last_message();
+    assert!(list.starts_with("/torrent1<code> | second"));
+    assert!(!list.contains("/torrent0"));
+}
+
+#[tokio::test]
+async fn test_torrent_page() {
+    let test_case = TestCase::new().await;
+    test_case
+        .fake()
+        .insert_torrent("aaaa", "first", "qbitbot,qbitbot:alice");
+    test_case.fake().set_ratio("aaaa", 0.5);
+
+    test_case.send("/torrent0").await;
+    let page = test_case.last_message();
+    let lines: Vec<&str> = page.lines().collect();
+    assert!(lines[0].starts_with("/torrent0<code> | first"), "{}", page);
+    assert_eq!(lines[1], "Save path: /downloads/");
+    assert!(lines[2].starts_with("Added: "), "{}", page);
+    assert_eq!(lines[3], "Ratio: 0.50");
+    assert_eq!(lines[4], "Added by: alice");
+}
+
+#[tokio::test]
+async fn test_publish_commands() {
+    let test_case = TestCase::new().await;
+    test_case.qbot().publish_commands().await;
+    let tg = test_case.tg();
+    // by default only the commands of viewers are suggested
+    let viewer = vec![
+        "back", "help", "instance", "list", "main", "mine", "torrent",
+    ];
+    let private = tg.commands(&CommandScope::AllPrivateChats).unwrap();
+    assert_eq!(private, viewer);
+    let group = tg.commands(&CommandScope::AllGroupChats).unwrap();
+    assert_eq!(group, viewer);
+
+    test_case.send_from("/main", ADMIN, 5).await;
+    let admin = tg.commands(&CommandScope::Chat { chat_id: 5 }).unwrap();
+    assert_eq!(
+        admin,
+        vec![
+            "audit", "back", "download", "help", "instance", "limit", "list", "main", "mine",
+            "pause", "reload", "resume", "torrent"
+        ]
+    );
+
+    test_case.send_from("/list", "BadTester", 42).await;
+    let hidden = tg.commands(&CommandScope::Chat { chat_id: 42 }).unwrap();
+    assert!(hidden.is_empty());
+}
@@ -1,4 +1,6 @@
-use common::{TestCase, MAGNET_LINK};
+use tokio::time::{sleep, Duration};
+
+use common::{TestCase, MAGNET_HASH, MAGNET_LINK};
 
 mod common;
 
@@ -18,3 +20,17 @@ Buttons:
     // item is already added
     test_case.check("FAIL");
 }
+
+#[tokio::test]
+async fn test_completion_notification() {
+    let test_case = TestCase::new().await;
+    test_case.send("/download").await;
+    test_case.send(MAGNET_LINK).await;
+    test_case.check("OK");
+    test_case.fake().set_progress(MAGNET_HASH, 0.5);
+    sleep(Duration::from_secs(2)).await;
+    test_case.check("OK");
+    test_case.fake().complete(MAGNET_HASH);
+    sleep(Duration::from_secs(2)).await;
+    test_case.check("torrent 60a2 is done");
+}
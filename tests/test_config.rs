@@ -0,0 +1,304 @@
+use std::time::Duration;
+
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, TestCase, ADMIN, MAGNET_HASH};
+use qbitbot::bot::config::{BackendKind, QbConfig, Role};
+use qbitbot::bot::messages::CommandScope;
+
+mod common;
+
+/// Write `text` to a temporary TOML file and load it
+fn load(name: &str, text: &str) -> Result<QbConfig, Vec<String>> {
+    let path = std::env::temp_dir().join(format!("qbitbot-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let config = QbConfig::load_path(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    config.map_err(|err| err.errors)
+}
+
+#[test]
+fn test_load_toml() {
+    let config = load(
+        "valid",
+        r#"
+token = "123:abc"
+state_file = "/var/lib/qbitbot/state.json"
+
+[[instances]]
+name = "seedbox"
+location = "https://seedbox.example.com"
+user = "admin"
+password = "secret"
+
+[[instances]]
+name = "nas"
+backend = "transmission"
+location = "http://nas:9091"
+user = "nas"
+password = "secret"
+
+[users]
+"@alice" = "admin"
+bob = "viewer"
+
+[notifications]
+check_interval = 10
+
+[display]
+list_sort = "ratio"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.token, "123:abc");
+    assert_eq!(config.state_file, "/var/lib/qbitbot/state.json");
+    assert_eq!(config.audit_file, "audit.jsonl");
+    assert_eq!(config.log_level, "info");
+    let names: Vec<&str> = config.instances.iter().map(|i| i.name.as_str()).collect();
+    assert_eq!(names, vec!["seedbox", "nas"]);
+    assert_eq!(config.instances[1].backend, BackendKind::Transmission);
+    assert_eq!(config.role("alice"), Some(Role::Admin));
+    assert_eq!(config.role("bob"), Some(Role::Viewer));
+    assert_eq!(config.role("eve"), None);
+    assert!(config.notifications.completed);
+    assert_eq!(config.notifications.check_interval, Duration::from_secs(10));
+    assert_eq!(config.display.list_sort.as_deref(), Some("ratio"));
+    assert!(config.webhook.is_none());
+}
+
+#[test]
+fn test_load_dotenv() {
+    let path = std::env::temp_dir().join(format!("qbitbot-{}.env", std::process::id()));
+    let text = "# older config\n\
+                TOKEN='123:abc'\n\
+                QBLOCATION=\"http://localhost:8080\"\n\
+                QBUSER=admin\n\
+                export QBPASS=secret\n\
+                ADMIN='erin  frank'\n";
+    std::fs::write(&path, text).unwrap();
+    let config = QbConfig::load_path(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config.token, "123:abc");
+    assert_eq!(config.instances[0].name, "default");
+    assert_eq!(config.instances[0].location, "http://localhost:8080");
+    assert_eq!(config.instances[0].password, "secret");
+    // names are separated by any whitespace, so there are no empty names
+    let mut users: Vec<&String> = config.users.keys().collect();
+    users.sort();
+    assert_eq!(users, vec!["erin", "frank"]);
+}
+
+#[test]
+fn test_errors_are_collected() {
+    let errors = load(
+        "invalid",
+        r#"
+[[instances]]
+name = "seedbox"
+backend = "deluge"
+location = "seedbox:8080"
+user = "admin"
+password = "secret"
+
+[[instances]]
+name = "seedbox"
+location = "http://seedbox:8080"
+user = "admin"
+
+[users]
+alice = "owner"
+
+[notifications]
+check_interval = 0
+
+[display]
+list_sort = "eta"
+
+[webhook]
+url = "https://example.com/qbitbot"
+listen = "localhost"
+
+[health]
+listen = "nowhere"
+"#,
+    )
+    .unwrap_err();
+    let wants = [
+        "token is not set, add it to the config or set TOKEN or TOKEN_FILE",
+        "instance seedbox backend: Unknown backend deluge",
+        "instance seedbox location should start with http:// or https://, got seedbox:8080",
+        "Instance seedbox is listed twice",
+        "instance seedbox password is not set, add it to the config or set SEEDBOX_QBPASS \
+         or SEEDBOX_QBPASS_FILE",
+        "User alice: Unknown role owner, expected admin|user|viewer",
+        "notifications check_interval should be positive",
+        "display list_sort should be one of name|size|progress|ratio, got eta",
+        "webhook listen localhost is not an address: invalid socket address syntax",
+        "webhook secret is not set, add it to the config or set WEBHOOK_SECRET \
+         or WEBHOOK_SECRET_FILE",
+        "health listen nowhere is not an address: invalid socket address syntax",
+    ];
+    for want in wants.iter() {
+        assert!(errors.iter().any(|err| err == want), "{:?}", errors);
+    }
+
+    let errors = load("typo", "tokne = \"123:abc\"").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("unknown field `tokne`"), "{:?}", errors);
+}
+
+#[test]
+fn test_api_tokens() {
+    let base = "token = \"123:abc\"\n\
+                [[instances]]\n\
+                name = \"default\"\n\
+                location = \"http://localhost:8080\"\n\
+                user = \"admin\"\n\
+                password = \"secret\"\n\
+                [users]\n\
+                alice = \"admin\"\n";
+    let config = load(
+        "api",
+        &format!(
+            "{}[api]\n[api.tokens.dashboard]\ntoken = \"abc\"\nuser = \"@alice\"\nchat_id = 42\n",
+            base
+        ),
+    )
+    .unwrap();
+    let api = config.api.unwrap();
+    assert_eq!(api.listen, ([127, 0, 0, 1], 8081).into());
+    assert_eq!(api.tokens[0].user, "alice");
+    assert_eq!(api.tokens[0].chat_id, Some(42));
+    assert!(!format!("{:?}", api).contains("abc"));
+
+    let errors = load(
+        "api-invalid",
+        &format!(
+            "{}[api.tokens.first]\nuser = \"bob\"\n[api.tokens.second]\ntoken = \"abc\"\nuser = \"alice\"\n",
+            base
+        ),
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            "api token first is not set, add token or token_file",
+            "api token first user bob is not in [users]",
+        ]
+    );
+}
+
+#[test]
+fn test_local_config_without_token() {
+    let path = std::env::temp_dir().join(format!("qbitbot-local-{}.toml", std::process::id()));
+    let path = path.to_str().unwrap();
+    let text = "[[instances]]\n\
+                name = \"local\"\n\
+                location = \"http://localhost:8080\"\n\
+                user = \"admin\"\n\
+                password = \"secret\"\n\
+                [users]\n\
+                alice = \"admin\"\n";
+    std::fs::write(path, text).unwrap();
+    let errors = QbConfig::load_path(path).unwrap_err().errors;
+    assert_eq!(
+        errors,
+        vec!["token is not set, add it to the config or set TOKEN or TOKEN_FILE"]
+    );
+    // the terminal frontend doesn't need it
+    let config = QbConfig::load_local(Some(path)).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(config.token, "");
+    assert_eq!(config.instances[0].name, "local");
+}
+
+#[tokio::test]
+async fn test_roles() {
+    let fake = FakeQbittorrent::start();
+    fake.insert_torrent(MAGNET_HASH, "torrent", "");
+    let mut conf = test_config(vec![instance_config("default", fake.location())]);
+    conf.users.insert(String::from("viewer"), Role::Viewer);
+    let test_case = TestCase::with_config(&conf).await;
+
+    test_case.send_from("/torrent0", "viewer", 1).await;
+    test_case.send_from("/pause", "viewer", 1).await;
+    test_case.check("You are not allowed to use /pause");
+    test_case.send_from("/download", "viewer", 1).await;
+    test_case.check("You are not allowed to use /download");
+    assert_eq!(fake.torrent(MAGNET_HASH).unwrap()["state"], "downloading");
+    test_case.send_from("/help", "viewer", 1).await;
+    let help = test_case.last_message();
+    assert!(help.contains("/list"), "{}", help);
+    assert!(!help.contains("/pause"), "{}", help);
+    assert!(!help.contains("/reload"), "{}", help);
+
+    // free text in the download menu opened by an admin is a download too
+    test_case.send_from("/download", ADMIN, 1).await;
+    test_case
+        .send_from("magnet:?xt=urn:btih:abc", "viewer", 1)
+        .await;
+    test_case.check("You are not allowed to use /download");
+}
+
+#[tokio::test]
+async fn test_reload() {
+    let fake = FakeQbittorrent::start();
+    let path = std::env::temp_dir().join(format!("qbitbot-reload-{}.toml", std::process::id()));
+    let path = path.to_str().unwrap();
+    let write = |users: &str, location: &str| {
+        let text = format!(
+            "token = \"123:abc\"\n\
+             [[instances]]\n\
+             name = \"reloadtest\"\n\
+             location = \"{}\"\n\
+             user = \"admin\"\n\
+             password = \"secret\"\n\
+             [users]\n\
+             {} = \"admin\"\n\
+             {}\n",
+            location, ADMIN, users
+        );
+        std::fs::write(path, text).unwrap();
+    };
+    write("", &fake.location());
+    let conf = QbConfig::load_path(path).unwrap();
+    let test_case = TestCase::with_config_file(&conf, path).await;
+
+    test_case.send_from("/main", "carol", 1).await;
+    test_case.check("You are not allowed to chat with me");
+
+    write("carol = \"viewer\"", &fake.location());
+    test_case.send("/reload").await;
+    test_case.check("Config is reloaded:\nUser carol is added as Viewer");
+    let suggested = || {
+        let scope = CommandScope::Chat { chat_id: 1 };
+        test_case.tg().commands(&scope).unwrap()
+    };
+    assert!(suggested().contains(&String::from("list")));
+    assert!(!suggested().contains(&String::from("download")));
+    test_case.send_from("/reload", "carol", 1).await;
+    test_case.check("You are not allowed to use /reload");
+    assert_eq!(test_case.qbot().config().role("carol"), Some(Role::Viewer));
+
+    write("carol = \"owner\"", "http://localhost:1");
+    test_case.send("/reload").await;
+    test_case.check(
+        "Invalid config:\n  - User carol: Unknown role owner, expected admin|user|viewer\n\
+         The running config is kept",
+    );
+    assert_eq!(test_case.qbot().config().role("carol"), Some(Role::Viewer));
+
+    write("carol = \"user\"", "http://localhost:1");
+    test_case.send("/reload").await;
+    test_case.check(
+        "Config is reloaded:\nUser carol is User instead of Viewer\n\
+         Changed instances is applied after restart",
+    );
+    assert!(suggested().contains(&String::from("download")));
+    assert!(!suggested().contains(&String::from("reload")));
+    // instances are kept until restart
+    assert_eq!(
+        test_case.qbot().config().instances[0].location,
+        fake.location()
+    );
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,122 @@
+//! Tests changing environment variables, which are shared by tests of one binary
+use qbitbot::bot::config::QbConfig;
+
+/// Write `text` to a temporary TOML file and load it
+fn load(name: &str, text: &str) -> Result<QbConfig, Vec<String>> {
+    let path = std::env::temp_dir().join(format!("qbitbot-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let config = QbConfig::load_path(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    config.map_err(|err| err.errors)
+}
+
+#[test]
+fn test_env_overrides() {
+    std::env::set_var("ENVTEST_QBPASS", "from-env");
+    std::env::set_var("ADMIN", "carol  dave ");
+    let config = load(
+        "env",
+        r#"
+token = "123:abc"
+
+[[instances]]
+name = "envtest"
+location = "http://localhost:8080"
+user = "admin"
+password = "from-file"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.instances[0].password, "from-env");
+    let mut admins: Vec<&String> = config.users.keys().collect();
+    admins.sort();
+    assert_eq!(admins, vec!["carol", "dave"]);
+}
+
+#[test]
+fn test_secret_files() {
+    let dir = std::env::temp_dir();
+    let token_path = dir.join(format!("qbitbot-token-{}", std::process::id()));
+    let pass_path = dir.join(format!("qbitbot-pass-{}", std::process::id()));
+    std::fs::write(&token_path, "123:from-file\n").unwrap();
+    std::fs::write(&pass_path, "hunter2\n").unwrap();
+    std::env::set_var("FILETEST_QBPASS_FILE", &pass_path);
+    let config = load(
+        "secrets",
+        &format!(
+            r#"
+token_file = "{}"
+
+[[instances]]
+name = "filetest"
+location = "http://localhost:8080"
+user = "admin"
+password = "overridden by FILETEST_QBPASS_FILE"
+
+[users]
+alice = "admin"
+"#,
+            token_path.display()
+        ),
+    )
+    .unwrap();
+    assert_eq!(config.token, "123:from-file");
+    assert_eq!(config.instances[0].password, "hunter2");
+
+    let debug = format!("{:?}", config);
+    assert!(!debug.contains("123:from-file"), "{}", debug);
+    assert!(!debug.contains("hunter2"), "{}", debug);
+    assert!(debug.contains("password: \"<redacted>\""), "{}", debug);
+
+    std::fs::remove_file(&pass_path).unwrap();
+    let errors = load(
+        "missing",
+        r#"
+token = "123:abc"
+token_file = "/run/credentials/token"
+
+[[instances]]
+name = "filetest"
+location = "http://localhost:8080"
+user = "admin"
+"#,
+    )
+    .unwrap_err();
+    assert!(errors.contains(&String::from("token is set both directly and with a file")));
+    let read_error = format!(
+        "Failed to read instance filetest password from {}",
+        pass_path.display()
+    );
+    assert!(
+        errors.iter().any(|err| err.starts_with(&read_error)),
+        "{:?}",
+        errors
+    );
+    std::fs::remove_file(&token_path).unwrap();
+}
+
+#[test]
+fn test_toml_ignores_dotenv() {
+    // other tests of this binary use absolute paths, so moving to another dir is safe
+    let dir = std::env::temp_dir().join(format!("qbitbot-dotenv-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".env"), "DOTENVTEST_QBPASS=from-dotenv\n").unwrap();
+    let path = dir.join("qbitbot.toml");
+    std::fs::write(
+        &path,
+        r#"
+token = "123:abc"
+
+[[instances]]
+name = "dotenvtest"
+location = "http://localhost:8080"
+user = "admin"
+password = "from-file"
+"#,
+    )
+    .unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    let config = QbConfig::load_path(path.to_str().unwrap()).unwrap();
+    assert_eq!(config.instances[0].password, "from-file");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,66 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, update, ADMIN};
+use qbitbot::bot::messages::{BotCommand, CommandScope, TelegramBackend};
+use qbitbot::bot::qbot::{MessageWrapper, QbitBot};
+
+mod common;
+
+/// Replies with their chats in the order they are sent
+#[derive(Clone, Default)]
+struct Replies(Arc<Mutex<Vec<(i64, String)>>>);
+
+impl Replies {
+    fn of(&self, chat_id: i64) -> Vec<String> {
+        let replies = self.0.lock().unwrap();
+        replies
+            .iter()
+            .filter(|(id, _)| *id == chat_id)
+            .map(|(_, text)| text.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl TelegramBackend for Replies {
+    async fn send_message(&self, chat_id: i64, message: MessageWrapper) {
+        self.0.lock().unwrap().push((chat_id, message.text));
+    }
+
+    async fn set_commands(&self, _: Vec<BotCommand>, _: CommandScope) {}
+}
+
+#[tokio::test]
+async fn test_chats_run_concurrently_in_order() {
+    let fake = FakeQbittorrent::start();
+    fake.insert_torrent("aaaa", "slow", "");
+    let conf = test_config(vec![instance_config("default", fake.location())]);
+    let replies = Replies::default();
+    let qbot = Arc::new(QbitBot::new(&conf, replies.clone()).await);
+    fake.set_delay(Duration::from_millis(300));
+
+    qbot.dispatch(update("/list", ADMIN, 1));
+    qbot.dispatch(update("/help", ADMIN, 1));
+    qbot.dispatch(update("/help", ADMIN, 2));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // the second chat doesn't wait for the slow list of the first one
+    assert_eq!(replies.of(2).len(), 1);
+    assert!(replies.of(1).is_empty());
+    // the worker of the second chat is dropped once its queue is empty
+    assert_eq!(qbot.active_chats(), 1);
+
+    assert!(qbot.shutdown(Duration::from_secs(5)).await);
+    assert_eq!(qbot.active_chats(), 0);
+    let first = replies.of(1);
+    assert_eq!(first.len(), 2);
+    assert!(
+        first[0].starts_with("/torrent0<code> | slow"),
+        "{:?}",
+        first
+    );
+    assert_eq!(first[1], replies.of(2)[0]);
+}
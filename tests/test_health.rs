@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, TestCase};
+use qbitbot::bot::config::{HealthConfig, MetricsConfig};
+use qbitbot::bot::health;
+use qbitbot::bot::metrics;
+
+mod common;
+
+async fn healthz(location: &str) -> (StatusCode, Value) {
+    let resp = reqwest::get(location).await.unwrap();
+    (resp.status(), resp.json::<Value>().await.unwrap())
+}
+
+#[test]
+fn test_backoff() {
+    assert_eq!(health::backoff(0), Duration::from_secs(0));
+    assert_eq!(health::backoff(1), Duration::from_secs(1));
+    assert_eq!(health::backoff(3), Duration::from_secs(4));
+    assert_eq!(health::backoff(10), Duration::from_secs(60));
+    assert_eq!(health::backoff(100), Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn test_healthz() {
+    let fake = FakeQbittorrent::start();
+    let conf = test_config(vec![
+        instance_config("default", fake.location()),
+        instance_config("down", String::from("http://localhost:1")),
+    ]);
+    let test_case = TestCase::with_config(&conf).await;
+    let config = MetricsConfig {
+        listen: ([127, 0, 0, 1], 0).into(),
+        torrents: false,
+    };
+    let server = metrics::bind(&config, test_case.qbot().clone()).unwrap();
+    let location = format!("http://{}/healthz", server.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let handle = tokio::spawn(server.serve(async {
+        stop_rx.await.ok();
+    }));
+
+    let (status, body) = healthz(&location).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["telegram"]["mode"], "polling");
+    assert_eq!(body["telegram"]["last_update_secs"], Value::Null);
+    let instances = body["instances"].as_array().unwrap();
+    assert_eq!(instances[0]["name"], "default");
+    assert_eq!(instances[0]["logged_in"], true);
+    assert_eq!(instances[0]["last_success_secs"], 0);
+    assert_eq!(instances[1]["name"], "down");
+    assert_eq!(instances[1]["logged_in"], false);
+    assert_eq!(instances[1]["last_success_secs"], Value::Null);
+    assert!(instances[1]["last_error"].is_string());
+
+    assert_eq!(health::polling_failed(), Duration::from_secs(1));
+    assert_eq!(health::polling_failed(), Duration::from_secs(2));
+    assert_eq!(health::polling_failed(), Duration::from_secs(4));
+    let (status, body) = healthz(&location).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "failing");
+    assert_eq!(body["telegram"]["ok"], false);
+    assert_eq!(body["telegram"]["consecutive_errors"], 3);
+
+    health::update_received();
+    let (status, body) = healthz(&location).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["telegram"]["last_update_secs"], 0);
+    assert_eq!(body["telegram"]["consecutive_errors"], 0);
+    // the next failure starts a new streak
+    assert_eq!(health::polling_failed(), Duration::from_secs(1));
+
+    stop_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_healthz_without_metrics() {
+    let test_case = TestCase::new().await;
+    let config = HealthConfig {
+        listen: ([127, 0, 0, 1], 0).into(),
+    };
+    let server = health::bind(&config, test_case.qbot().clone()).unwrap();
+    let location = format!("http://{}", server.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let handle = tokio::spawn(server.serve(async {
+        stop_rx.await.ok();
+    }));
+
+    let (_, body) = healthz(&format!("{}/healthz", location)).await;
+    assert_eq!(body["instances"][0]["name"], "default");
+    let resp = reqwest::get(format!("{}/metrics", location)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    stop_tx.send(()).unwrap();
+    handle.await.unwrap().unwrap();
+}
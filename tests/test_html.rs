@@ -0,0 +1,68 @@
+use common::TestCase;
+use qbitbot::bot::html::{split, strip, to_ansi, MESSAGE_LIMIT};
+
+mod common;
+
+#[test]
+fn test_split_keeps_lines_and_tags() {
+    let line = "/torrent0<code> | name | 1 Mb | 100% | done</code>";
+    let text = vec![line; 200].join("\n");
+    let parts = split(&text, MESSAGE_LIMIT, true);
+    assert!(parts.len() > 1);
+    for part in &parts {
+        assert!(part.chars().count() <= MESSAGE_LIMIT);
+        assert!(part.trim_end().ends_with("</code>"));
+    }
+    assert_eq!(parts.concat().lines().count(), 200);
+
+    let text = format!("<pre>{}</pre>", "x\n".repeat(MESSAGE_LIMIT));
+    let parts = split(&text, MESSAGE_LIMIT, true);
+    for part in &parts {
+        assert!(part.starts_with("<pre>"));
+        assert!(part.ends_with("</pre>"));
+        assert!(part.chars().count() <= MESSAGE_LIMIT);
+    }
+}
+
+#[test]
+fn test_split_long_line() {
+    let text = "&amp;".repeat(MESSAGE_LIMIT);
+    let parts = split(&text, MESSAGE_LIMIT, true);
+    for part in &parts {
+        assert!(part.starts_with("&amp;") && part.ends_with("&amp;"));
+    }
+    assert_eq!(parts.concat(), text);
+
+    let parts = split(&"a".repeat(10), 4, false);
+    assert_eq!(parts, vec!["aaaa", "aaaa", "aa"]);
+}
+
+#[test]
+fn test_terminal_rendering() {
+    let text = "<b>nas</b> /instance1\n/torrent0<code> | Tom &amp; Jerry</code>";
+    assert_eq!(strip(text), "nas /instance1\n/torrent0 | Tom & Jerry");
+    assert_eq!(
+        to_ansi(text),
+        "\x1b[1mnas\x1b[0m /instance1\n/torrent0\x1b[36m | Tom & Jerry\x1b[0m"
+    );
+    // the outer style is restored after the inner one
+    assert_eq!(
+        to_ansi("<b>a<i>b</i>c</b>"),
+        "\x1b[1ma\x1b[3mb\x1b[0m\x1b[1mc\x1b[0m"
+    );
+    assert_eq!(
+        to_ansi("<a href=\"x\">link</a> 1 < 2"),
+        "\x1b[4mlink\x1b[0m 1 < 2"
+    );
+}
+
+#[tokio::test]
+async fn test_escape_torrent_name() {
+    let test_case = TestCase::new().await;
+    test_case
+        .fake()
+        .insert_torrent("aaaa", "Tom & Jerry <1080p>", "");
+    test_case.send("/list").await;
+    let list = test_case.last_message();
+    assert!(list.starts_with("/torrent0<code> | Tom &amp; Jerry &lt;1080p&gt;  | "));
+}
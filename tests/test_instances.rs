@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, RutebotMock, TestCase, MAGNET_HASH, MAGNET_LINK};
+use qbitbot::bot::qbot::QbitBot;
+
+mod common;
+
+#[tokio::test]
+async fn test_switch_instances() {
+    let seedbox = FakeQbittorrent::start();
+    let nas = FakeQbittorrent::start();
+    let conf = test_config(vec![
+        instance_config("seedbox", seedbox.location()),
+        instance_config("nas", nas.location()),
+    ]);
+    let test_case = TestCase::with_config(&conf).await;
+
+    test_case.send("/instance").await;
+    test_case.check(
+        r#"/instance0 seedbox (active)
+/instance1 nas
+
+Buttons:
+
+/back"#,
+    );
+    test_case.send("/instance1").await;
+    test_case.check(
+        r#"/instance0 seedbox
+/instance1 nas (active)
+
+Buttons:
+
+/back"#,
+    );
+
+    test_case.send("/download").await;
+    test_case.send(MAGNET_LINK).await;
+    test_case.check("OK");
+    assert!(nas.torrent(MAGNET_HASH).is_some());
+    assert!(seedbox.torrent(MAGNET_HASH).is_none());
+
+    test_case.send("/list").await;
+    let list = test_case.last_message();
+    assert!(list.starts_with("<b>nas</b> /instance1\n/torrent0<code> | torrent 60a2"));
+
+    test_case.send("/list all").await;
+    let list = test_case.last_message();
+    assert!(list.starts_with(
+        "<b>seedbox</b> /instance0\nThere are no torrents\n\n<b>nas</b> /instance1\n/torrent0"
+    ));
+}
+
+#[tokio::test]
+async fn test_start_logs_in_later() {
+    let fake = FakeQbittorrent::start();
+    fake.set_delay(Duration::from_millis(300));
+    let conf = test_config(vec![instance_config("default", fake.location())]);
+
+    // the bot is built before the slow instance answers
+    let qbot = QbitBot::start(&conf, RutebotMock::default());
+    assert_eq!(fake.logins(), 0);
+    assert_eq!(qbot.instances().len(), 1);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while fake.logins() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the instance is not logged in");
+}
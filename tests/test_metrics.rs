@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use common::{TestCase, MAGNET_LINK};
+use qbitbot::bot::config::MetricsConfig;
+use qbitbot::bot::metrics;
+
+mod common;
+
+#[tokio::test]
+async fn test_metrics() {
+    let test_case = TestCase::new().await;
+    test_case.fake().insert_torrent("aaaa", "first", "");
+    test_case.send("/list").await;
+    test_case.send("/torrent7").await;
+    test_case.send_from("/list", "stranger", 2).await;
+    test_case.send("/download").await;
+    test_case.send(MAGNET_LINK).await;
+
+    let config = MetricsConfig {
+        listen: ([127, 0, 0, 1], 0).into(),
+        torrents: true,
+    };
+    let server = metrics::bind(&config, test_case.qbot().clone()).unwrap();
+    let location = format!("http://{}", server.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let handle = tokio::spawn(server.serve(async {
+        stop_rx.await.ok();
+    }));
+
+    let resp = reqwest::get(format!("{}/metrics", location)).await.unwrap();
+    assert!(resp.status().is_success());
+    let text = resp.text().await.unwrap();
+    let wants = [
+        "qbitbot_updates_total{command=\"list\",outcome=\"ok\"} 1",
+        "qbitbot_updates_total{command=\"list\",outcome=\"forbidden\"} 1",
+        "qbitbot_updates_total{command=\"download\",outcome=\"ok\"} 2",
+        "qbitbot_torrent_client_request_duration_seconds_count{endpoint=\"/sync/maindata\"} ",
+        "qbitbot_torrent_client_request_duration_seconds_bucket{endpoint=\"/torrents/add\",le=\"+Inf\"} 1",
+        // the updates were processed directly, without chat workers
+        "qbitbot_active_chats 0\n",
+        "qbitbot_active_watchers 1\n",
+        "qbitbot_download_speed_bytes{instance=\"default\"} 0\n",
+        "qbitbot_torrents{instance=\"default\",state=\"downloading\"} 2\n",
+    ];
+    for want in wants.iter() {
+        assert!(text.contains(want), "{} is not in\n{}", want, text);
+    }
+    // a missing torrent is a reply, not a failure of the command
+    assert!(text.contains("qbitbot_updates_total{command=\"torrent\",outcome=\"ok\"} 1"));
+
+    let resp = reqwest::get(format!("{}/other", location)).await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    stop_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
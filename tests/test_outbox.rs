@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use qbitbot::bot::messages::{
+    BotCommand, CommandScope, TelegramApi, TelegramBackend, TelegramError,
+};
+use qbitbot::bot::outbox::{Limits, Outbox};
+use qbitbot::bot::qbot::MessageWrapper;
+
+#[derive(Clone, Default)]
+struct FakeApi {
+    sent: Arc<Mutex<Vec<(i64, String, Instant)>>>,
+    /// Errors returned before the next successful sends
+    errors: Arc<Mutex<VecDeque<TelegramError>>>,
+}
+
+impl FakeApi {
+    fn sent(&self) -> Vec<(i64, String)> {
+        let sent = self.sent.lock().unwrap();
+        sent.iter()
+            .map(|(chat_id, text, _)| (*chat_id, text.clone()))
+            .collect()
+    }
+
+    fn fail_with(&self, err: TelegramError) {
+        self.errors.lock().unwrap().push_back(err);
+    }
+}
+
+#[async_trait]
+impl TelegramApi for FakeApi {
+    async fn send_text(&self, chat_id: i64, text: &str, _: bool) -> Result<(), TelegramError> {
+        if let Some(err) = self.errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+        let sent = (chat_id, text.to_string(), Instant::now());
+        self.sent.lock().unwrap().push(sent);
+        Ok(())
+    }
+
+    async fn set_commands(&self, _: &[BotCommand], _: &CommandScope) -> Result<(), TelegramError> {
+        Ok(())
+    }
+}
+
+const LIMITS: Limits = Limits {
+    global: Duration::from_millis(5),
+    chat: Duration::from_millis(50),
+    attempts: 3,
+};
+
+fn text(text: &str) -> MessageWrapper {
+    MessageWrapper {
+        text: text.to_string(),
+        parse_mode: None,
+    }
+}
+
+async fn wait_sent(api: &FakeApi, count: usize) {
+    for _ in 0..100 {
+        if api.sent().len() >= count {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Sent only {:?}", api.sent());
+}
+
+#[tokio::test]
+async fn test_chat_rate_limit() {
+    let api = FakeApi::default();
+    let outbox = Outbox::with_limits(api.clone(), LIMITS);
+    outbox.send_message(1, text("first")).await;
+    outbox.send_message(1, text("second")).await;
+    outbox.send_message(2, text("other chat")).await;
+    wait_sent(&api, 3).await;
+
+    let sent = api.sent.lock().unwrap().clone();
+    let chat_times: Vec<Instant> = sent
+        .iter()
+        .filter(|(chat_id, _, _)| *chat_id == 1)
+        .map(|(_, _, time)| *time)
+        .collect();
+    assert!(chat_times[1] - chat_times[0] >= LIMITS.chat);
+    // the other chat doesn't wait for the first one
+    assert_eq!(sent[1].1, "other chat");
+}
+
+#[tokio::test]
+async fn test_coalesce_notifications() {
+    let api = FakeApi::default();
+    let outbox = Outbox::with_limits(api.clone(), LIMITS);
+    outbox.send_message(1, text("menu")).await;
+    outbox.send_notification(1, text("a is done")).await;
+    outbox.send_notification(1, text("b is done")).await;
+    outbox.send_notification(1, text("c is done")).await;
+    wait_sent(&api, 2).await;
+    tokio::time::sleep(LIMITS.chat * 2).await;
+    assert_eq!(
+        api.sent(),
+        vec![
+            (1, String::from("menu")),
+            (1, String::from("a is done\nb is done\nc is done"))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_errors() {
+    let api = FakeApi::default();
+    let outbox = Outbox::with_limits(api.clone(), LIMITS);
+
+    api.fail_with(TelegramError::RetryAfter(Duration::from_millis(100)));
+    let start = Instant::now();
+    outbox.send_message(1, text("after flood")).await;
+    wait_sent(&api, 1).await;
+    assert!(api.sent.lock().unwrap()[0].2 - start >= Duration::from_millis(100));
+
+    api.fail_with(TelegramError::Transient(String::from("timeout")));
+    outbox.send_message(1, text("after timeout")).await;
+    wait_sent(&api, 2).await;
+
+    api.fail_with(TelegramError::ChatUnavailable(String::from("blocked")));
+    outbox.send_message(2, text("blocked")).await;
+    outbox.send_message(2, text("also dropped")).await;
+    tokio::time::sleep(LIMITS.chat).await;
+    outbox.send_message(3, text("not blocked")).await;
+    wait_sent(&api, 3).await;
+
+    // only the malformed message is dropped
+    api.fail_with(TelegramError::Permanent(String::from("bad markup")));
+    outbox.send_message(4, text("malformed")).await;
+    outbox.send_message(4, text("after malformed")).await;
+    wait_sent(&api, 4).await;
+    tokio::time::sleep(LIMITS.chat * 2).await;
+    assert_eq!(
+        api.sent(),
+        vec![
+            (1, String::from("after flood")),
+            (1, String::from("after timeout")),
+            (3, String::from("not blocked")),
+            (4, String::from("after malformed"))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_notification_fallback() {
+    let api = FakeApi::default();
+    let outbox = Outbox::with_limits(api.clone(), LIMITS);
+
+    // the user has never started the private chat, the group gets the notification
+    api.fail_with(TelegramError::ChatUnavailable(String::from(
+        "can't initiate",
+    )));
+    outbox
+        .send_notification_or(7, -100, text("a is done"))
+        .await;
+    wait_sent(&api, 1).await;
+    outbox
+        .send_notification_or(8, -100, text("b is done"))
+        .await;
+    wait_sent(&api, 2).await;
+    assert_eq!(
+        api.sent(),
+        vec![
+            (-100, String::from("a is done")),
+            (8, String::from("b is done"))
+        ]
+    );
+}
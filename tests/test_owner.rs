@@ -0,0 +1,79 @@
+use rutebot::responses::Update;
+use serde_json::json;
+
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, TestCase, MAGNET_HASH, MAGNET_LINK};
+use qbitbot::bot::config::Role;
+use qbitbot::bot::qbot::NOT_OWNER_MESSAGE;
+use qbitbot::bot::watchers::Watchers;
+
+mod common;
+
+const CAROL_ID: i64 = 7;
+const GROUP_ID: i64 = -100;
+
+/// Message of carol in the group chat
+fn group_update(text: &str) -> Update {
+    let message = json!({
+        "message_id": 0,
+        "date": 0,
+        "from": {"id": CAROL_ID, "is_bot": false, "first_name": "Carol", "username": "carol"},
+        "chat": {"id": GROUP_ID, "type": "group"},
+        "text": text
+    });
+    serde_json::from_value(json!({"update_id": 0, "message": message})).unwrap()
+}
+
+#[tokio::test]
+async fn test_owner() {
+    let fake = FakeQbittorrent::start();
+    let mut conf = test_config(vec![instance_config("default", fake.location())]);
+    conf.users.insert(String::from("carol"), Role::User);
+    conf.own_torrents_only = true;
+    let test_case = TestCase::with_config(&conf).await;
+    let qbot = test_case.qbot();
+
+    qbot.process_message(group_update("/download")).await;
+    qbot.process_message(group_update(MAGNET_LINK)).await;
+    test_case.check("OK");
+    assert_eq!(
+        fake.torrent(MAGNET_HASH).unwrap()["tags"],
+        "qbitbot,qbitbot:carol"
+    );
+    // completion goes to the private chat of the adder, the group is notified if it's unavailable
+    let path = std::env::temp_dir().join(format!("qbitbot-owner-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    qbot.save_state(path);
+    let watches = Watchers::load(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(watches.len(), 1);
+    assert_eq!(watches[0].chat_id, CAROL_ID);
+    assert_eq!(watches[0].fallback_chat_id, Some(GROUP_ID));
+
+    fake.insert_torrent("aaaa", "other", "qbitbot,qbitbot:dave");
+    qbot.process_message(group_update("/mine")).await;
+    let mine = test_case.last_message();
+    assert!(
+        mine.starts_with("/torrent0<code> | torrent 60a2"),
+        "{}",
+        mine
+    );
+    assert!(!mine.contains("/torrent1"), "{}", mine);
+
+    qbot.process_message(group_update("/pause 1")).await;
+    test_case.check(NOT_OWNER_MESSAGE);
+    // unknown torrents are refused, not passed on unchecked
+    qbot.process_message(group_update("/pause 5")).await;
+    test_case.check("There is no torrent with this id");
+    assert_eq!(fake.torrent("aaaa").unwrap()["state"], "downloading");
+    qbot.process_message(group_update("/pause 0")).await;
+    test_case.check("OK");
+
+    // admins change any torrent
+    test_case.send("/pause 1").await;
+    test_case.check("OK");
+    test_case.send("/mine").await;
+    assert!(test_case
+        .last_message()
+        .starts_with("There are no torrents"));
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use common::{MAGNET_LINK, RutebotMock, TestCase};
+use qbitbot::bot::audit::AuditLog;
 use qbitbot::bot::config::QbConfig;
 use qbitbot::bot::qb_chat::MenuValue::*;
 use qbitbot::bot::qb_chat::QbChat;
@@ -12,9 +13,10 @@ mod common;
 async fn run_simple_tests() {
     let test_case = TestCase::new().await;
     let tg_arc = test_case.get_tg_arc();
-    test_menu_walk(tg_arc.clone()).await;
-    test_download(tg_arc.clone()).await;
-    test_torrent_page(tg_arc).await
+    let conf = test_case.config();
+    test_menu_walk(conf, tg_arc.clone()).await;
+    test_download(conf, tg_arc.clone()).await;
+    test_torrent_page(conf, tg_arc).await
 }
 
 pub async fn check_goto(chat: &mut QbChat, mock_rbot: Arc<RutebotMock>, text: &str) {
@@ -27,14 +29,14 @@ pub async fn check_goto(chat: &mut QbChat, mock_rbot: Arc<RutebotMock>, text: &s
     })
 }
 
-pub async fn create_qbchat_mock() -> QbChat {
-    let conf = QbConfig::load_path("tests/.env_tests");
-    let qbclient = QbClient::new(&conf).await;
-    QbChat::new(0, qbclient)
+pub async fn create_qbchat_mock(conf: &QbConfig) -> QbChat {
+    let qbclient = QbClient::new(&conf.instances[0]).await;
+    let audit = AuditLog::new(&conf.audit_file);
+    QbChat::new(0, vec![qbclient], Arc::default(), Arc::new(audit))
 }
 
-async fn test_menu_walk(tg_mock: Arc<RutebotMock>) {
-    let mut chat = create_qbchat_mock().await;
+async fn test_menu_walk(conf: &QbConfig, tg_mock: Arc<RutebotMock>) {
+    let mut chat = create_qbchat_mock(conf).await;
     assert_eq!(chat.get_menu_pos(), Main);
     check_goto(&mut chat,tg_mock.clone(), "/help").await;
     assert_eq!(chat.get_menu_pos(), Help);
@@ -48,8 +50,8 @@ async fn test_menu_walk(tg_mock: Arc<RutebotMock>) {
     assert_eq!(chat.get_menu_pos(), Main);
 }
 
-async fn test_download(tg_mock: Arc<RutebotMock>) {
-    let mut chat = create_qbchat_mock().await;
+async fn test_download(conf: &QbConfig, tg_mock: Arc<RutebotMock>) {
+    let mut chat = create_qbchat_mock(conf).await;
     check_goto(&mut chat, tg_mock.clone(), "/download").await;
     assert_eq!(chat.get_menu_pos(), Download);
     check_goto(&mut chat, tg_mock.clone(), MAGNET_LINK).await;
@@ -58,8 +60,8 @@ async fn test_download(tg_mock: Arc<RutebotMock>) {
     assert_eq!(chat.get_menu_pos(), Download);
 }
 
-async fn test_torrent_page(tg_mock: Arc<RutebotMock>) {
-    let mut chat = create_qbchat_mock().await;
+async fn test_torrent_page(conf: &QbConfig, tg_mock: Arc<RutebotMock>) {
+    let mut chat = create_qbchat_mock(conf).await;
     check_goto(&mut chat, tg_mock.clone(), "/download").await;
     assert_eq!(chat.get_menu_pos(), Download);
     check_goto(&mut chat, tg_mock.clone(), MAGNET_LINK).await;
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use common::{update, TestCase, ADMIN};
+use qbitbot::bot::qbot::QbitBot;
+use qbitbot::bot::watchers::{Watch, Watchers};
+
+mod common;
+
+#[tokio::test]
+async fn test_shutdown_finishes_updates() {
+    let test_case = TestCase::new().await;
+    let qbot = test_case.qbot();
+    qbot.dispatch(update("/help", ADMIN, 1));
+    qbot.dispatch(update("/main", ADMIN, 1));
+    assert!(qbot.shutdown(Duration::from_secs(5)).await);
+    test_case.check("Main menu\n\nButtons:\n/help\n/list\n/download\n/instance\n/back");
+
+    qbot.dispatch(update("/list", ADMIN, 1));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    test_case.check("Main menu\n\nButtons:\n/help\n/list\n/download\n/instance\n/back");
+}
+
+#[tokio::test]
+async fn test_restore_watches() {
+    let test_case = TestCase::new().await;
+    let fake = test_case.fake();
+    fake.insert_torrent("aaaa", "saved", "");
+    let path = std::env::temp_dir().join(format!("qbitbot-state-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let watchers = Watchers::default();
+    watchers.add(Watch {
+        chat_id: 1,
+        fallback_chat_id: None,
+        instance: String::from("default"),
+        hash: String::from("aaaa"),
+        name: String::from("saved"),
+    });
+    watchers.save(path).unwrap();
+    assert_eq!(Watchers::load(path).unwrap(), watchers.list());
+
+    // the restarted bot keeps watching and saves the watch again on shutdown
+    let qbot = QbitBot::new(test_case.config(), test_case.tg().clone()).await;
+    qbot.restore_state(path);
+    qbot.save_state(path);
+    assert_eq!(Watchers::load(path).unwrap(), watchers.list());
+
+    fake.complete("aaaa");
+    test_case.wait_message("saved is done").await;
+    qbot.save_state(path);
+    assert!(Watchers::load(path).unwrap().is_empty());
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,8 +1,7 @@
 use serde::Serialize;
 
 use common::TestCase;
-use qbitbot::bot::config::QbConfig;
-use qbitbot::bot::qb_client::QbClient;
+use qbitbot::bot::backend::QbittorrentBackend;
 
 mod common;
 
@@ -11,7 +10,7 @@ async fn run_simple_tests() {
     let test_case = TestCase::new().await;
     test_not_admin(&test_case).await;
     test_help(&test_case).await;
-    test_client_start().await;
+    test_client_start(&test_case).await;
 }
 
 async fn test_not_admin(test_case: &TestCase) {
@@ -21,10 +20,19 @@ async fn test_not_admin(test_case: &TestCase) {
 
 async fn test_help(test_case: &TestCase) {
     test_case.send("/help").await;
-    let wants = r#"/download - Start downloading by link or attached file
+    let wants = r#"/audit [n] - Show recent actions that changed torrents
+/back - Go to previous menu
+/download - Start downloading by link or attached file (also /add)
 /help - Show help for all commands
-/list - List torrents
-/main - Go to main menu
+/instance [id] - Show and switch torrent client instances
+/limit [id] [dl=&lt;speed&gt;] [up=&lt;speed&gt;] - Limit torrent speed, e.g. dl=2M up=512K, 0 removes the limit
+/list [all|downloading|seeding|completed|paused] [sort=name|size|progress|ratio] - List torrents, "all" lists torrents of all instances (also /ls)
+/main - Go to main menu (also /start)
+/mine - List torrents you added
+/pause [id] - Pause torrent (also /stop)
+/reload - Reload users, notification and display settings from the config
+/resume [id] - Resume torrent (also /unpause)
+/torrent &lt;id&gt; - Show torrent page
 
 Buttons:
 
@@ -37,8 +45,9 @@ struct EmptyAction{
     test: i32
 }
 
-async fn test_client_start() {
-    let conf = QbConfig::load_path("tests/.env_tests");
-    let client = QbClient::new(&conf).await;
-    client.qpost("/app/version", EmptyAction{test: 1}).await.unwrap();
+async fn test_client_start(test_case: &TestCase) {
+    let conf = test_case.config();
+    // there is no session yet, so this also checks re-login on 403
+    let backend = QbittorrentBackend::new(&conf.instances[0]);
+    backend.qpost("/app/version", EmptyAction{test: 1}).await.unwrap();
 }
@@ -0,0 +1,99 @@
+use serde_json::json;
+
+use qbitbot::bot::commands::list::QListAction;
+use qbitbot::bot::commands::QbCommandAction;
+use qbitbot::bot::sync::SyncState;
+
+fn full_update() -> serde_json::Value {
+    json!({
+        "rid": 1,
+        "full_update": true,
+        "torrents": {
+            "bbbb": {"name": "second", "size": 2097152, "progress": 0.5, "eta": 60, "completion_on": -1, "state": "downloading"},
+            "aaaa": {"name": "first", "size": 1048576, "progress": 1.0, "eta": 8640000, "completion_on": 1600000000, "state": "uploading"}
+        },
+        "categories": {"movies": {"name": "movies", "savePath": "/movies"}},
+        "tags": ["qbitbot"],
+        "server_state": {"dl_info_speed": 100, "up_info_speed": 10}
+    })
+}
+
+#[test]
+fn test_full_update() {
+    let mut state = SyncState::default();
+    state.apply(&full_update()).unwrap();
+    assert_eq!(state.rid(), 1);
+    assert_eq!(
+        state.hashes().into_iter().collect::<Vec<_>>(),
+        vec!["aaaa", "bbbb"]
+    );
+    assert_eq!(state.torrent("aaaa").unwrap()["hash"], "aaaa");
+    assert!(state.categories().contains_key("movies"));
+    assert!(state.tags().contains("qbitbot"));
+    assert_eq!(state.server_state()["dl_info_speed"], 100);
+}
+
+#[test]
+fn test_partial_update() {
+    let mut state = SyncState::default();
+    state.apply(&full_update()).unwrap();
+    state
+        .apply(&json!({
+            "rid": 2,
+            "torrents": {"bbbb": {"progress": 1.0, "state": "uploading"}},
+            "torrents_removed": ["aaaa"],
+            "categories_removed": ["movies"],
+            "tags": ["music"],
+            "tags_removed": ["qbitbot"],
+            "server_state": {"dl_info_speed": 0}
+        }))
+        .unwrap();
+    assert_eq!(state.rid(), 2);
+    assert!(state.torrent("aaaa").is_none());
+    let torrent = state.torrent("bbbb").unwrap();
+    assert_eq!(torrent["progress"], 1.0);
+    assert_eq!(torrent["state"], "uploading");
+    // untouched fields are kept
+    assert_eq!(torrent["name"], "second");
+    assert!(state.categories().is_empty());
+    assert_eq!(state.tags().iter().collect::<Vec<_>>(), vec!["music"]);
+    assert_eq!(state.server_state()["dl_info_speed"], 0);
+    assert_eq!(state.server_state()["up_info_speed"], 10);
+}
+
+#[test]
+fn test_full_update_drops_old_state() {
+    let mut state = SyncState::default();
+    state.apply(&full_update()).unwrap();
+    state
+        .apply(&json!({
+            "rid": 7,
+            "full_update": true,
+            "torrents": {"cccc": {"name": "third"}}
+        }))
+        .unwrap();
+    assert_eq!(state.hashes().into_iter().collect::<Vec<_>>(), vec!["cccc"]);
+    assert!(state.tags().is_empty());
+    assert!(state.server_state().is_empty());
+}
+
+#[test]
+fn test_bad_response() {
+    let mut state = SyncState::default();
+    assert!(state.apply(&json!({"torrents": {}})).is_err());
+    assert!(state.apply(&json!([])).is_err());
+}
+
+#[test]
+fn test_list_from_state() {
+    let mut state = SyncState::default();
+    state.apply(&full_update()).unwrap();
+    let list = QListAction::from_state(&state);
+    assert_eq!(list.get_records().len(), 2);
+    assert_eq!(list.get_record_by_num(0).unwrap().get_hash(), "aaaa");
+    assert_eq!(list.get_record_by_num(1).unwrap().get_name(), "second");
+    assert_eq!(
+        QListAction::from_state(&SyncState::default()).action_result_to_string(),
+        "There are no torrents"
+    );
+}
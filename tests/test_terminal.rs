@@ -0,0 +1,59 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use common::stand_in::qbittorrent::FakeQbittorrent;
+use common::{instance_config, test_config, ADMIN};
+use qbitbot::bot::qbot::QbitBot;
+use qbitbot::bot::terminal::{self, Terminal};
+
+mod common;
+
+/// Output of the terminal shared with the test
+#[derive(Clone, Default)]
+struct Screen(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Screen {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_repl() {
+    let fake = FakeQbittorrent::start();
+    fake.insert_torrent("aaaa", "Tom & Jerry", "");
+    let conf = test_config(vec![instance_config("default", fake.location())]);
+    let screen = Screen::default();
+    let qbot = QbitBot::new(&conf, Terminal::new(screen.clone(), false)).await;
+
+    let input: &[u8] = b"/list\n\n  /torrent0  \n";
+    terminal::run(&qbot, ADMIN, input).await.unwrap();
+    let text = screen.text();
+    assert!(text.starts_with("/torrent0 | Tom & Jerry "), "{}", text);
+    // blank lines are skipped and the torrent page is the second reply
+    assert_eq!(
+        text.matches("/torrent0 | Tom & Jerry ").count(),
+        2,
+        "{}",
+        text
+    );
+    assert_eq!(text.matches("\n\nButtons:\n").count(), 2, "{}", text);
+    assert!(!text.contains("<code>"));
+
+    let screen = Screen::default();
+    let qbot = QbitBot::new(&conf, Terminal::new(screen.clone(), true)).await;
+    terminal::run(&qbot, "stranger", &b"/list\n"[..])
+        .await
+        .unwrap();
+    assert_eq!(screen.text(), "You are not allowed to chat with me\n\n");
+}
@@ -0,0 +1,42 @@
+use std::net::TcpListener;
+
+use common::{instance_config, test_config, TestCase};
+use qbitbot::bot::qbot::UNREACHABLE_MESSAGE;
+
+mod common;
+
+/// Address of a local port that refuses connections
+fn refused_location() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[tokio::test]
+async fn test_unreachable_qbittorrent() {
+    let conf = test_config(vec![instance_config("default", refused_location())]);
+    let test_case = TestCase::with_config(&conf).await;
+
+    test_case.send("/list").await;
+    test_case.check(UNREACHABLE_MESSAGE);
+
+    test_case.send("/help").await;
+    // the page is shown without the torrent client, its text is checked by test_simple
+    assert!(test_case
+        .last_message()
+        .contains("/help - Show help for all commands"));
+
+    test_case.send("/list").await;
+    test_case.check(UNREACHABLE_MESSAGE);
+
+    test_case.send("/back").await;
+    test_case.check(
+        r#"Main menu
+
+Buttons:
+/help
+/list
+/download
+/instance
+/back"#,
+    );
+}
@@ -0,0 +1,69 @@
+use reqwest::StatusCode;
+use serde_json::json;
+use tokio::sync::oneshot;
+
+use common::{TestCase, ADMIN};
+use qbitbot::bot::config::WebhookConfig;
+use qbitbot::bot::webhook::{self, SECRET_HEADER};
+
+mod common;
+
+const SECRET: &str = "test-secret";
+
+fn update_json(text: &str) -> serde_json::Value {
+    json!({
+        "update_id": 1,
+        "message": {
+            "message_id": 1,
+            "date": 0,
+            "from": {"id": 1, "is_bot": false, "first_name": "Test", "username": ADMIN},
+            "chat": {"id": 1, "type": "private"},
+            "text": text
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_webhook() {
+    let test_case = TestCase::new().await;
+    let config = WebhookConfig {
+        url: String::from("https://example.com/qbitbot"),
+        listen: ([127, 0, 0, 1], 0).into(),
+        path: String::from("/qbitbot"),
+        secret: String::from(SECRET),
+    };
+    let webhook = webhook::bind(&config, test_case.qbot().clone()).unwrap();
+    let location = format!("http://{}", webhook.local_addr());
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(webhook.serve(async {
+        stop_rx.await.ok();
+    }));
+
+    let client = reqwest::Client::new();
+    let post = |path: &str, secret: &str, body: String| {
+        client
+            .post(format!("{}{}", location, path))
+            .header(SECRET_HEADER, secret)
+            .body(body)
+            .send()
+    };
+    let update = update_json("/main").to_string();
+
+    let resp = post("/qbitbot", "wrong", update.clone()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = post("/other", SECRET, update.clone()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    // a broken update is dropped, Telegram would send it again otherwise
+    let resp = post("/qbitbot", SECRET, String::from("{}")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test_case.last_message(), "");
+
+    let resp = post("/qbitbot", SECRET, update).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    test_case
+        .wait_message("Main menu\n\nButtons:\n/help\n/list\n/download\n/instance\n/back")
+        .await;
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}